@@ -0,0 +1,22 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use tgs::{op_code::Isa, program::Program};
+
+fuzz_target!(|input: &[u8]| {
+    // the first byte picks the ISA so both decoders get fuzzed
+    let Some((&first, bytes)) = input.split_first() else {
+        return;
+    };
+    let isa = if first & 1 == 0 { Isa::Classic } else { Isa::Extended };
+    let (lenient, errors) = Program::new_lenient("fuzz", bytes, isa);
+    match Program::with_isa("fuzz", bytes, isa) {
+        Ok(program) => {
+            // a program that loads is valid apart from branches past its end
+            assert_eq!(program.op_codes(), lenient.op_codes());
+            let reloaded = Program::with_isa("fuzz", &program.to_bytes(), isa).expect("encoded program does not load");
+            assert_eq!(reloaded.op_codes(), program.op_codes());
+        }
+        Err(_) => assert!(!errors.is_empty()),
+    }
+});
@@ -0,0 +1,32 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use tgs::{
+    debugger::Debugger,
+    op_code::Isa,
+    program::Program,
+    registers::Register,
+    tgs::{MachineStatus, Tgs},
+};
+
+/// Instructions to run each input for
+const BUDGET: u64 = 10_000;
+
+fuzz_target!(|input: &[u8]| {
+    // the first byte picks the ISA so the extended instructions get run too
+    let Some((&first, bytes)) = input.split_first() else {
+        return;
+    };
+    let isa = if first & 1 == 0 { Isa::Classic } else { Isa::Extended };
+    let (program, _) = Program::new_lenient("fuzz", bytes, isa);
+    let mut debugger = Debugger::new(Tgs::with_isa(isa), program);
+    debugger.enable_history(64);
+    *debugger.tgs_mut().register_mut_ref(Register::BA) = std::num::Wrapping(bytes.len() as u8 & 1);
+    debugger.run(BUDGET, None);
+    let executed = debugger.instructions();
+    while debugger.step_back() {}
+    if executed > 0 {
+        // every rewound instruction was executed by a running machine
+        assert_eq!(debugger.tgs().status(), MachineStatus::Running);
+    }
+});
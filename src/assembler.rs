@@ -0,0 +1,443 @@
+//! Turns TGS assembly back into the 3 byte instruction encoding.
+//!
+//! The accepted syntax is a superset of what [`OpCode`]'s `Display` impl emits,
+//! one instruction per line, e.g. `MOV %R0, %BA` or `BR  $0`. Empty lines and `;`
+//! comments are ignored. On top of that the assembler understands:
+//!
+//! - labels: `loop:` on its own line or in front of an instruction, usable as a
+//!   branch target with `BNE loop` or `BNE $loop`. A label used as a value is its
+//!   PC in the 256 instruction page, only labels in the page of the instruction
+//!   can be used that way, `BRF` takes labels in any page
+//! - constants: `.equ LIMIT, 10`, usable as a value with `CMP %R0, $LIMIT`
+//! - register aliases: `.alias counter, %R3`, usable as `%counter`
+//!
+//! Values can be written in decimal, hex (`0x0f`) or binary (`0b0000_1111`).
+//! Labels and constants share a namespace and can be used before they are defined.
+//! Instructions and registers of the extended ISA are rejected when assembling for
+//! the classic one.
+
+use std::{collections::HashMap, str::FromStr};
+
+use crate::{
+    error::{Error, Result},
+    op_code::{Isa, OpCode},
+    registers::Register,
+};
+
+enum Operand {
+    Register(Register),
+    Value(u8),
+    /// `[%R1]`, a RAM address held in a register
+    Indirect(Register),
+    /// `[$16]`, a RAM address
+    Address(u8),
+}
+
+#[derive(Default)]
+struct Symbols {
+    labels: HashMap<String, usize>,
+    constants: HashMap<String, u8>,
+    aliases: HashMap<String, Register>,
+}
+
+impl Symbols {
+    fn is_defined(&self, name: &str) -> bool {
+        self.labels.contains_key(name) || self.constants.contains_key(name) || self.aliases.contains_key(name)
+    }
+
+    fn define_label(&mut self, line: usize, name: &str, index: usize) -> Result<()> {
+        self.check_new(line, name)?;
+        self.labels.insert(name.into(), index);
+        Ok(())
+    }
+
+    fn define_constant(&mut self, line: usize, name: &str, value: u8) -> Result<()> {
+        self.check_new(line, name)?;
+        self.constants.insert(name.into(), value);
+        Ok(())
+    }
+
+    fn define_alias(&mut self, line: usize, name: &str, register: Register) -> Result<()> {
+        self.check_new(line, name)?;
+        self.aliases.insert(name.into(), register);
+        Ok(())
+    }
+
+    fn check_new(&self, line: usize, name: &str) -> Result<()> {
+        if !is_identifier(name) {
+            return Err(invalid(line, format!("'{name}' is not a valid name")));
+        }
+        if self.is_defined(name) || Register::from_str(&name.to_ascii_uppercase()).is_ok() {
+            return Err(Error::DuplicateSymbol { line, name: name.into() });
+        }
+        Ok(())
+    }
+
+    fn register(&self, line: usize, name: &str) -> Result<Register> {
+        if let Some(register) = self.aliases.get(name) {
+            return Ok(*register);
+        }
+        Register::from_str(&name.to_ascii_uppercase()).map_err(|_| invalid(line, format!("Unknown register '%{name}'")))
+    }
+
+    /// Value of a constant, or PC of a label in `page`
+    fn value(&self, line: usize, name: &str, page: usize) -> Result<u8> {
+        if let Some(value) = self.constants.get(name) {
+            return Ok(*value);
+        }
+        match self.labels.get(name) {
+            Some(&index) if index >> 8 == page => Ok(index as u8),
+            Some(&index) => Err(Error::LabelOutOfRange {
+                line,
+                name: name.into(),
+                index,
+            }),
+            None => Err(Error::UndefinedSymbol { line, name: name.into() }),
+        }
+    }
+
+    /// Instruction index of a label or constant in any page
+    fn index(&self, line: usize, name: &str) -> Result<usize> {
+        if let Some(value) = self.constants.get(name) {
+            return Ok(*value as usize);
+        }
+        self.labels
+            .get(name)
+            .copied()
+            .ok_or_else(|| Error::UndefinedSymbol { line, name: name.into() })
+    }
+}
+
+/// Assembles the source into a list of op codes the ISA can decode
+pub fn assemble(source: &str, isa: Isa) -> Result<Vec<OpCode>> {
+    let mut symbols = Symbols::default();
+    let mut instructions = Vec::new();
+
+    for (i, line) in source.lines().enumerate() {
+        let line_num = i + 1;
+        let mut line = strip_comment(line).trim();
+
+        while let Some((label, rest)) = split_label(line) {
+            symbols.define_label(line_num, label, instructions.len())?;
+            line = rest.trim_start();
+        }
+
+        if line.is_empty() {
+            continue;
+        }
+        if line.starts_with('.') {
+            parse_directive(&mut symbols, line_num, line)?;
+        } else {
+            instructions.push((line_num, line));
+        }
+    }
+
+    if instructions.len() > isa.max_len() {
+        return Err(Error::ProgramTooLong { len: instructions.len(), max: isa.max_len() });
+    }
+    instructions
+        .into_iter()
+        .enumerate()
+        .map(|(index, (line_num, line))| {
+            let op_code = parse_instruction(&symbols, line_num, index, line)?;
+            OpCode::decode(op_code.encode(), isa)
+                .map_err(|_| invalid(line_num, format!("'{line}' needs the extended ISA")))
+        })
+        .collect()
+}
+
+/// Assembles the source into the bytes of a `.bin` program for the ISA
+pub fn assemble_to_bytes(source: &str, isa: Isa) -> Result<Vec<u8>> {
+    let op_codes = assemble(source, isa)?;
+    Ok(op_codes.iter().flat_map(OpCode::encode).collect())
+}
+
+fn invalid(line: usize, msg: String) -> Error {
+    Error::InvalidAssembly { line, msg }
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn strip_comment(line: &str) -> &str {
+    line.split_once(';').map_or(line, |(code, _)| code)
+}
+
+fn split_label(line: &str) -> Option<(&str, &str)> {
+    let (label, rest) = line.split_once(':')?;
+    let label = label.trim();
+    is_identifier(label).then_some((label, rest))
+}
+
+fn parse_directive(symbols: &mut Symbols, line: usize, directive: &str) -> Result<()> {
+    let (name, rest) = directive.split_once(char::is_whitespace).unwrap_or((directive, ""));
+    let args: Vec<&str> = rest.split(',').map(str::trim).collect();
+
+    match (name, args.as_slice()) {
+        (".equ", [symbol, value]) => {
+            let value = value.strip_prefix('$').unwrap_or(value);
+            let value = parse_value(value).ok_or_else(|| invalid(line, format!("Invalid value '{value}', expected 0 to 255")))?;
+            symbols.define_constant(line, symbol, value)
+        }
+        (".alias", [symbol, register]) => {
+            let register = register.strip_prefix('%').unwrap_or(register);
+            let register = symbols.register(line, register)?;
+            symbols.define_alias(line, symbol, register)
+        }
+        _ => Err(invalid(line, format!("Invalid directive '{directive}'"))),
+    }
+}
+
+fn parse_instruction(symbols: &Symbols, line: usize, index: usize, instruction: &str) -> Result<OpCode> {
+    let (mnemonic, rest) = instruction.split_once(char::is_whitespace).unwrap_or((instruction, ""));
+    if mnemonic.eq_ignore_ascii_case("BRF") {
+        return parse_far_target(symbols, line, rest.trim()).map(OpCode::BRF);
+    }
+    let operands = rest
+        .split(',')
+        .map(str::trim)
+        .filter(|o| !o.is_empty())
+        .map(|o| parse_operand(symbols, line, index >> 8, o))
+        .collect::<Result<Vec<_>>>()?;
+
+    let op_code = match (mnemonic.to_ascii_uppercase().as_str(), operands.as_slice()) {
+        ("HLT", []) => OpCode::HLT,
+
+        ("ADD", [Operand::Register(t), Operand::Register(s)]) => OpCode::ADD(*t, *s),
+        ("SUB", [Operand::Register(t), Operand::Register(s)]) => OpCode::SUB(*t, *s),
+        ("LSH", [Operand::Register(t), Operand::Register(s)]) => OpCode::LSH(*t, *s),
+        ("RSH", [Operand::Register(t), Operand::Register(s)]) => OpCode::RSH(*t, *s),
+        ("AND", [Operand::Register(t), Operand::Register(s)]) => OpCode::AND(*t, *s),
+        ("OR",  [Operand::Register(t), Operand::Register(s)]) => OpCode::OR(*t, *s),
+        ("XOR", [Operand::Register(t), Operand::Register(s)]) => OpCode::XOR(*t, *s),
+        ("CMP", [Operand::Register(t), Operand::Register(s)]) => OpCode::CMP(*t, *s),
+        ("MOV", [Operand::Register(t), Operand::Register(s)]) => OpCode::MOV(*t, *s),
+
+        ("ADD", [Operand::Register(t), Operand::Value(v)]) => OpCode::ADDV(*t, *v),
+        ("SUB", [Operand::Register(t), Operand::Value(v)]) => OpCode::SUBV(*t, *v),
+        ("LSH", [Operand::Register(t), Operand::Value(v)]) => OpCode::LSHV(*t, *v),
+        ("RSH", [Operand::Register(t), Operand::Value(v)]) => OpCode::RSHV(*t, *v),
+        ("AND", [Operand::Register(t), Operand::Value(v)]) => OpCode::ANDV(*t, *v),
+        ("OR",  [Operand::Register(t), Operand::Value(v)]) => OpCode::ORV(*t, *v),
+        ("XOR", [Operand::Register(t), Operand::Value(v)]) => OpCode::XORV(*t, *v),
+        ("CMP", [Operand::Register(t), Operand::Value(v)]) => OpCode::CMPV(*t, *v),
+        ("MOV", [Operand::Register(t), Operand::Value(v)]) => OpCode::MOVV(*t, *v),
+
+        ("BR",  [Operand::Value(v)]) => OpCode::BR(*v),
+        ("BE",  [Operand::Value(v)]) => OpCode::BE(*v),
+        ("BNE", [Operand::Value(v)]) => OpCode::BNE(*v),
+        ("BG",  [Operand::Value(v)]) => OpCode::BG(*v),
+        ("BL",  [Operand::Value(v)]) => OpCode::BL(*v),
+        ("BHI", [Operand::Value(v)]) => OpCode::BHI(*v),
+        ("BLO", [Operand::Value(v)]) => OpCode::BLO(*v),
+
+        ("LD", [Operand::Register(t), Operand::Indirect(s)]) => OpCode::LD(*t, *s),
+        ("LD", [Operand::Register(t), Operand::Address(v)])  => OpCode::LDV(*t, *v),
+        ("ST", [Operand::Indirect(t), Operand::Register(s)]) => OpCode::ST(*t, *s),
+        ("ST", [Operand::Address(v), Operand::Register(s)])  => OpCode::STV(*v, *s),
+
+        ("CALL", [Operand::Value(v)])    => OpCode::CALL(*v),
+        ("RET",  [])                     => OpCode::RET,
+        ("PUSH", [Operand::Register(s)]) => OpCode::PUSH(*s),
+        ("POP",  [Operand::Register(t)]) => OpCode::POP(*t),
+
+        _ => return Err(invalid(line, format!("Invalid instruction '{instruction}'"))),
+    };
+
+    Ok(op_code)
+}
+
+fn parse_operand(symbols: &Symbols, line: usize, page: usize, operand: &str) -> Result<Operand> {
+    if let Some(address) = operand.strip_prefix('[').and_then(|o| o.strip_suffix(']')) {
+        return match parse_operand(symbols, line, page, address.trim())? {
+            Operand::Register(register) => Ok(Operand::Indirect(register)),
+            Operand::Value(value) => Ok(Operand::Address(value)),
+            _ => Err(invalid(line, format!("Invalid address '{operand}'"))),
+        };
+    }
+    if let Some(register) = operand.strip_prefix('%') {
+        symbols.register(line, register).map(Operand::Register)
+    } else {
+        let value = operand.strip_prefix('$').unwrap_or(operand);
+        if is_identifier(value) {
+            symbols.value(line, value, page).map(Operand::Value)
+        } else {
+            parse_value(value)
+                .map(Operand::Value)
+                .ok_or_else(|| invalid(line, format!("Invalid value '{operand}', expected 0 to 255")))
+        }
+    }
+}
+
+/// Target of a far branch, an instruction index in any page
+fn parse_far_target(symbols: &Symbols, line: usize, operand: &str) -> Result<u16> {
+    let value = operand.strip_prefix('$').unwrap_or(operand);
+    let index = if is_identifier(value) {
+        symbols.index(line, value)?
+    } else {
+        parse_index(value).ok_or_else(|| invalid(line, format!("Invalid target '{operand}', expected 0 to 65535")))? as usize
+    };
+    u16::try_from(index).map_err(|_| invalid(line, format!("Target '{operand}' is past the last page")))
+}
+
+fn parse_index(value: &str) -> Option<u16> {
+    if let Some(hex) = value.strip_prefix("0x") {
+        u16::from_str_radix(&hex.replace('_', ""), 16).ok()
+    } else if let Some(bin) = value.strip_prefix("0b") {
+        u16::from_str_radix(&bin.replace('_', ""), 2).ok()
+    } else {
+        value.parse().ok()
+    }
+}
+
+pub(crate) fn parse_value(value: &str) -> Option<u8> {
+    if let Some(hex) = value.strip_prefix("0x") {
+        u8::from_str_radix(&hex.replace('_', ""), 16).ok()
+    } else if let Some(bin) = value.strip_prefix("0b") {
+        u8::from_str_radix(&bin.replace('_', ""), 2).ok()
+    } else {
+        value.parse().ok()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        error::Error,
+        op_code::{Isa, OpCode},
+        program::Program,
+        registers::Register,
+    };
+
+    use super::{assemble, assemble_to_bytes};
+
+    #[test]
+    fn test_round_trip_assets() {
+        let assets: [&[u8]; 3] = [
+            include_bytes!("../assets/hi.bin"),
+            include_bytes!("../assets/demo1.bin"),
+            include_bytes!("../assets/demo2.bin"),
+        ];
+        for bytes in assets {
+            let program = Program::new("", bytes).expect("failed to load asset");
+            let source = program.get_readable_program();
+            let assembled = assemble_to_bytes(&source, Isa::Classic).expect("failed to assemble disassembly");
+            assert_eq!(assembled, bytes);
+        }
+    }
+
+    #[test]
+    fn test_assemble() {
+        let source = "MOV %R0, %BA ; read button a\n\nOR  %D0, $0x0f\nBNE $9\nhlt\n";
+        let op_codes = assemble(source, Isa::Classic).expect("failed to assemble");
+        assert_eq!(
+            op_codes,
+            [
+                OpCode::MOV(Register::R0, Register::BA),
+                OpCode::ORV(Register::D0, 15),
+                OpCode::BNE(9),
+                OpCode::HLT,
+            ]
+        );
+        assert_eq!(&assemble_to_bytes(source, Isa::Classic).expect("failed to assemble")[9..], [0, 0, 0]);
+    }
+
+    #[test]
+    fn test_assemble_symbols() {
+        let source = r#"
+.equ H, 0b0111_0110
+.alias digit, %D1
+
+start:  MOV %R0, %BA
+        ADD %R0, %BB
+        CMP %R0, $0
+        BNE show
+        MOV %digit, $0
+        BR  start
+show:
+        MOV %digit, $H
+        BR  $start
+"#;
+        let op_codes = assemble(source, Isa::Classic).expect("failed to assemble");
+        assert_eq!(
+            op_codes,
+            [
+                OpCode::MOV(Register::R0, Register::BA),
+                OpCode::ADD(Register::R0, Register::BB),
+                OpCode::CMPV(Register::R0, 0),
+                OpCode::BNE(6),
+                OpCode::MOVV(Register::D1, 0),
+                OpCode::BR(0),
+                OpCode::MOVV(Register::D1, 0b0111_0110),
+                OpCode::BR(0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_assemble_errors() {
+        for source in ["MOV %R0", "MOV %R9, $1", "ADD %R0, $256", "BR %R0", "NOP", ".equ X, 300", ".org 4"] {
+            let err = assemble(source, Isa::Classic).expect_err(source);
+            assert!(matches!(err, Error::InvalidAssembly { line: 1, .. }), "{source}: {err}");
+        }
+    }
+
+    #[test]
+    fn test_symbol_errors() {
+        let err = assemble("BR nowhere", Isa::Classic).expect_err("undefined label");
+        assert!(matches!(err, Error::UndefinedSymbol { line: 1, .. }), "{err}");
+
+        let err = assemble("a: BR a\na: BR a", Isa::Classic).expect_err("duplicate label");
+        assert!(matches!(err, Error::DuplicateSymbol { line: 2, .. }), "{err}");
+
+        let err = assemble(".equ a, 1\na: BR a", Isa::Classic).expect_err("label shadowing a constant");
+        assert!(matches!(err, Error::DuplicateSymbol { line: 2, .. }), "{err}");
+
+        let err = assemble(".alias R1, %R2", Isa::Classic).expect_err("alias shadowing a register");
+        assert!(matches!(err, Error::DuplicateSymbol { line: 1, .. }), "{err}");
+
+        let source = format!("BR far\n{}far: BR far\n", "MOV %R0, $0\n".repeat(255));
+        let err = assemble(&source, Isa::Extended).expect_err("label past 255");
+        assert!(matches!(err, Error::LabelOutOfRange { line: 1, index: 256, .. }), "{err}");
+    }
+
+    #[test]
+    fn test_assemble_isa() {
+        for source in ["LD %R0, [$1]", "CALL $0", "RET", "PUSH %R1", "BRF $0", "MOV %R0, %PG", "MOV %TM, $1"] {
+            let source = format!("HLT\n{source}");
+            let err = assemble(&source, Isa::Classic).expect_err(&source);
+            assert!(matches!(err, Error::InvalidAssembly { line: 2, .. }), "{source}: {err}");
+            assert_eq!(assemble(&source, Isa::Extended).expect("failed to assemble").len(), 2);
+        }
+
+        let source = "HLT\n".repeat(257);
+        let err = assemble(&source, Isa::Classic).expect_err("more instructions than PC can address");
+        assert!(matches!(err, Error::ProgramTooLong { len: 257, max: 256 }), "{err}");
+        assert_eq!(assemble(&source, Isa::Extended).expect("failed to assemble").len(), 257);
+    }
+
+    #[test]
+    fn test_assemble_pages() {
+        let source = format!(
+            "BRF far\nnear: CALL near\n{}far: BR far\nCALL sub\nBRF near\nsub: RET\n",
+            "MOV %R0, $0\n".repeat(254)
+        );
+        let op_codes = assemble(&source, Isa::Extended).expect("failed to assemble far branches");
+        assert_eq!(op_codes[..2], [OpCode::BRF(256), OpCode::CALL(1)]);
+        assert_eq!(op_codes[256..], [OpCode::BR(0), OpCode::CALL(3), OpCode::BRF(1), OpCode::RET]);
+
+        // near branches and calls only reach labels in their own page
+        let padding = "MOV %R0, $0\n".repeat(255);
+        let source = format!("near: HLT\n{padding}BE sub\nsub: RET\n");
+        let op_codes = assemble(&source, Isa::Extended).expect("failed to assemble");
+        assert_eq!(op_codes[256], OpCode::BE(1));
+        for branch in ["BR near", "CALL near"] {
+            let source = format!("near: HLT\n{padding}{branch}\n");
+            let err = assemble(&source, Isa::Extended).expect_err(branch);
+            assert!(matches!(err, Error::LabelOutOfRange { line: 257, index: 0, .. }), "{branch}: {err}");
+        }
+    }
+}
@@ -0,0 +1,262 @@
+//! Conditions that stop execution of a [`Tgs`].
+//!
+//! - PC breakpoints stop before the instruction at an index is executed
+//! - conditions like `R3 == 9` stop after the instruction that makes them true
+//! - watchpoints stop after an instruction writes or changes a register
+
+use std::{collections::BTreeSet, fmt::Display, str::FromStr};
+
+use crate::{
+    assembler::parse_value,
+    error::Error,
+    op_code::OpCode,
+    registers::Register,
+    tgs::Tgs,
+};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Comparison {
+    const ALL: [(&'static str, Comparison); 6] = [
+        ("==", Comparison::Eq),
+        ("!=", Comparison::Ne),
+        ("<=", Comparison::Le),
+        (">=", Comparison::Ge),
+        ("<", Comparison::Lt),
+        (">", Comparison::Gt),
+    ];
+
+    pub fn compare(&self, a: u8, b: u8) -> bool {
+        match self {
+            Comparison::Eq => a == b,
+            Comparison::Ne => a != b,
+            Comparison::Lt => a < b,
+            Comparison::Le => a <= b,
+            Comparison::Gt => a > b,
+            Comparison::Ge => a >= b,
+        }
+    }
+
+    fn symbol(&self) -> &'static str {
+        Self::ALL
+            .iter()
+            .find_map(|(s, c)| (c == self).then_some(*s))
+            .unwrap_or("==")
+    }
+}
+
+/// A register compared against a value, written like `R3 == 9` or `%D0 != $0x3f`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Condition {
+    pub register: Register,
+    pub comparison: Comparison,
+    pub value: u8,
+}
+
+impl Condition {
+    pub fn is_met(&self, tgs: &Tgs) -> bool {
+        self.comparison.compare(tgs.register(self.register).0, self.value)
+    }
+}
+
+impl FromStr for Condition {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::InvalidCondition(s.into());
+        let (symbol, comparison) = Comparison::ALL
+            .iter()
+            .find(|(symbol, _)| s.contains(symbol))
+            .ok_or_else(invalid)?;
+        let (register, value) = s.split_once(symbol).ok_or_else(invalid)?;
+
+        let register = register.trim();
+        let register = register.strip_prefix('%').unwrap_or(register);
+        let register = Register::from_str(&register.to_ascii_uppercase()).map_err(|_| invalid())?;
+        let value = value.trim();
+        let value = parse_value(value.strip_prefix('$').unwrap_or(value)).ok_or_else(invalid)?;
+
+        Ok(Self {
+            register,
+            comparison: *comparison,
+            value,
+        })
+    }
+}
+
+impl Display for Condition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {} {}", self.register, self.comparison.symbol(), self.value)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum WatchKind {
+    /// Any instruction writing the register, even with the same value
+    Write,
+    /// Only writes that change the value of the register
+    Change,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub register: Register,
+    pub kind: WatchKind,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BreakReason {
+    Breakpoint(usize),
+    Condition(Condition),
+    Written(Register, u8),
+    Changed(Register, u8, u8),
+}
+
+impl Display for BreakReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BreakReason::Breakpoint(pc) => write!(f, "breakpoint at {pc:03}"),
+            BreakReason::Condition(condition) => write!(f, "condition {condition}"),
+            BreakReason::Written(r, v) => write!(f, "{r} written with {v}"),
+            BreakReason::Changed(r, old, new) => write!(f, "{r} changed from {old} to {new}"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Breakpoints {
+    pc: BTreeSet<usize>,
+    conditions: Vec<Condition>,
+    watchpoints: Vec<Watchpoint>,
+}
+
+impl Breakpoints {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_pc(&mut self, pc: usize) {
+        self.pc.insert(pc);
+    }
+
+    pub fn remove_pc(&mut self, pc: usize) {
+        self.pc.remove(&pc);
+    }
+
+    pub fn toggle_pc(&mut self, pc: usize) {
+        if !self.pc.remove(&pc) {
+            self.pc.insert(pc);
+        }
+    }
+
+    pub fn has_pc(&self, pc: usize) -> bool {
+        self.pc.contains(&pc)
+    }
+
+    pub fn add_condition(&mut self, condition: Condition) {
+        self.conditions.push(condition);
+    }
+
+    pub fn add_watchpoint(&mut self, register: Register, kind: WatchKind) {
+        self.watchpoints.push(Watchpoint { register, kind });
+    }
+
+    /// Checked before executing the instruction at PC
+    pub fn check_before(&self, tgs: &Tgs) -> Option<BreakReason> {
+        let pc = tgs.address();
+        self.has_pc(pc).then_some(BreakReason::Breakpoint(pc))
+    }
+
+    /// Returns true if there are watchpoints or conditions, which need the state before an instruction
+    pub fn watches_state(&self) -> bool {
+        !self.conditions.is_empty() || !self.watchpoints.is_empty()
+    }
+
+    /// Checked after `op_code` turned the `before` state into `after`
+    pub fn check_after(&self, before: &Tgs, after: &Tgs, op_code: &OpCode) -> Option<BreakReason> {
+        let written = op_code.written_register();
+        for watchpoint in &self.watchpoints {
+            let register = watchpoint.register;
+            let (old, new) = (before.register(register).0, after.register(register).0);
+            match watchpoint.kind {
+                WatchKind::Write if written == Some(register) => return Some(BreakReason::Written(register, new)),
+                WatchKind::Change if old != new => return Some(BreakReason::Changed(register, old, new)),
+                _ => {}
+            }
+        }
+
+        self.conditions
+            .iter()
+            .find(|c| c.is_met(after) && !c.is_met(before))
+            .map(|c| BreakReason::Condition(*c))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::num::Wrapping;
+
+    use crate::{op_code::OpCode, registers::Register, tgs::Tgs};
+
+    use super::{BreakReason, Breakpoints, Comparison, Condition, WatchKind};
+
+    #[test]
+    fn test_parse_condition() {
+        let condition: Condition = "R3 == 9".parse().expect("failed to parse");
+        assert_eq!(
+            condition,
+            Condition {
+                register: Register::R3,
+                comparison: Comparison::Eq,
+                value: 9
+            }
+        );
+        let condition: Condition = "%d0>=$0x3f".parse().expect("failed to parse");
+        assert_eq!(condition.comparison, Comparison::Ge);
+        assert_eq!(condition.value, 0x3f);
+        assert_eq!(condition.to_string(), "D0 >= 63");
+
+        for s in ["R3 = 9", "R9 == 1", "R3 == 256", "== 1"] {
+            assert!(s.parse::<Condition>().is_err(), "{s}");
+        }
+    }
+
+    #[test]
+    fn test_check() {
+        let mut breakpoints = Breakpoints::new();
+        breakpoints.add_pc(1);
+        assert!(!breakpoints.watches_state());
+        breakpoints.add_condition("R3 == 9".parse().expect("failed to parse"));
+        breakpoints.add_watchpoint(Register::D0, WatchKind::Write);
+        breakpoints.add_watchpoint(Register::R1, WatchKind::Change);
+        assert!(breakpoints.watches_state());
+
+        let mut tgs = Tgs::new();
+        assert_eq!(breakpoints.check_before(&tgs), None);
+        *tgs.register_mut_ref(Register::PC) = Wrapping(1);
+        assert_eq!(breakpoints.check_before(&tgs), Some(BreakReason::Breakpoint(1)));
+
+        let mut step = |op_code: OpCode| {
+            let before = tgs.clone();
+            tgs.process_instruction(op_code);
+            breakpoints.check_after(&before, &tgs, &op_code)
+        };
+        assert_eq!(step(OpCode::MOVV(Register::R3, 8)), None);
+        assert_eq!(
+            step(OpCode::ADDV(Register::R3, 1)),
+            Some(BreakReason::Condition("R3 == 9".parse().expect("failed to parse")))
+        );
+        assert_eq!(step(OpCode::MOVV(Register::R3, 9)), None);
+        assert_eq!(step(OpCode::MOVV(Register::D0, 0)), Some(BreakReason::Written(Register::D0, 0)));
+        assert_eq!(step(OpCode::MOVV(Register::R1, 0)), None);
+        assert_eq!(step(OpCode::MOVV(Register::R1, 2)), Some(BreakReason::Changed(Register::R1, 0, 2)));
+    }
+}
@@ -0,0 +1,228 @@
+//! Basic blocks and the control-flow graph of a [`Program`], exportable to
+//! Graphviz DOT.
+//!
+//! A block starts at instruction 0, at every branch target and after every
+//! branch, and ends with a branch or right before the next block. Branches to
+//! the end of the program or past it lead to a single `end` node. A CALL has
+//! an edge to the subroutine and one to the instruction it returns to, a RET
+//! has no edges as its target depends on the stack.
+//!
+//! ```sh
+//! tgs cfg assets/demo1.bin | dot -Tsvg > demo1.svg
+//! ```
+
+use crate::{disassembler::label_name, op_code::OpCode, program::Program};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum EdgeKind {
+    /// A conditional branch is taken
+    Taken,
+    /// A conditional branch is not taken
+    NotTaken,
+    /// An unconditional branch, or falling through to the next block
+    Always,
+    /// A CALL to a subroutine
+    Call,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Edge {
+    /// Index of the first instruction of the target block, the program length for `end`
+    pub to: usize,
+    pub kind: EdgeKind,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BasicBlock {
+    /// Index of the first instruction
+    pub start: usize,
+    /// Index after the last instruction
+    pub end: usize,
+    pub edges: Vec<Edge>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Cfg {
+    blocks: Vec<BasicBlock>,
+    len: usize,
+}
+
+impl Cfg {
+    pub fn new(program: &Program) -> Self {
+        let op_codes = program.op_codes();
+        let len = op_codes.len();
+
+        let mut leaders = vec![false; len + 1];
+        leaders[0] = true;
+        for (index, op_code) in op_codes.iter().enumerate() {
+            if let Some(target) = op_code.jump_target(index) {
+                leaders[target.min(len)] = true;
+            }
+            if op_code.jump_target(index).is_some() || !op_code.falls_through() {
+                leaders[index + 1] = true;
+            }
+        }
+
+        let starts: Vec<usize> = (0..len).filter(|&i| leaders[i]).collect();
+        let blocks = starts
+            .iter()
+            .enumerate()
+            .map(|(i, &start)| {
+                let end = starts.get(i + 1).copied().unwrap_or(len);
+                let last = &op_codes[end - 1];
+                let edges = match (last.jump_target(end - 1), last.falls_through()) {
+                    (Some(target), true) if !last.is_conditional() => vec![
+                        Edge { to: target.min(len), kind: EdgeKind::Call },
+                        Edge { to: end, kind: EdgeKind::Always },
+                    ],
+                    (Some(target), true) => vec![
+                        Edge { to: target.min(len), kind: EdgeKind::Taken },
+                        Edge { to: end, kind: EdgeKind::NotTaken },
+                    ],
+                    (Some(target), false) => vec![Edge { to: target.min(len), kind: EdgeKind::Always }],
+                    (None, true) => vec![Edge { to: end, kind: EdgeKind::Always }],
+                    (None, false) => vec![],
+                };
+                BasicBlock { start, end, edges }
+            })
+            .collect();
+
+        Self { blocks, len }
+    }
+
+    /// Blocks in program order
+    pub fn blocks(&self) -> &[BasicBlock] {
+        &self.blocks
+    }
+
+    /// Returns the block containing the instruction at `index`
+    pub fn block_of(&self, index: usize) -> Option<&BasicBlock> {
+        self.blocks.iter().find(|b| (b.start..b.end).contains(&index))
+    }
+
+    /// Renders the graph with the disassembly of every block as its label
+    pub fn to_dot(&self, program: &Program) -> String {
+        let mut dot = format!("digraph \"{}\" {{\n", escape(&program.get_name()));
+        dot.push_str("    node [shape=box fontname=monospace];\n");
+        for block in &self.blocks {
+            let mut label = format!("{}:\\l", label_name(block.start));
+            for (index, op_code) in program.op_codes()[block.start..block.end].iter().enumerate() {
+                let index = block.start + index;
+                label.push_str(&format!("{index:03}: {}\\l", escape(&op_code.to_string())));
+            }
+            dot.push_str(&format!("    {} [label=\"{label}\"];\n", self.node(block.start)));
+        }
+        if self.blocks.iter().flat_map(|b| &b.edges).any(|e| e.to == self.len) {
+            dot.push_str("    end [shape=doublecircle];\n");
+        }
+        for block in &self.blocks {
+            for edge in &block.edges {
+                let attrs = match edge.kind {
+                    EdgeKind::Taken => " [color=darkgreen]",
+                    EdgeKind::NotTaken => " [color=red style=dashed]",
+                    EdgeKind::Always => "",
+                    EdgeKind::Call => " [color=blue style=bold]",
+                };
+                dot.push_str(&format!("    {} -> {}{attrs};\n", self.node(block.start), self.node(edge.to)));
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    fn node(&self, start: usize) -> String {
+        if start == self.len {
+            "end".into()
+        } else {
+            label_name(start)
+        }
+    }
+}
+
+/// Instructions that can execute right after the one at `index`, for a CALL the subroutine and
+/// the instruction it returns to
+pub(crate) fn successors(op_codes: &[OpCode], index: usize) -> impl Iterator<Item = usize> + '_ {
+    let op_code = &op_codes[index];
+    let next = op_code.falls_through().then_some(index + 1);
+    let target = op_code.jump_target(index);
+    next.into_iter().chain(target).filter(|&i| i < op_codes.len())
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{assembler::assemble_to_bytes, op_code::Isa, program::Program};
+
+    use super::{BasicBlock, Cfg, Edge, EdgeKind};
+
+    #[test]
+    fn test_hi() {
+        let program = Program::new("hi.bin", include_bytes!("../assets/hi.bin")).expect("failed to load hi.bin");
+        let cfg = Cfg::new(&program);
+        assert_eq!(
+            cfg.blocks(),
+            [
+                BasicBlock {
+                    start: 0,
+                    end: 4,
+                    edges: vec![Edge { to: 9, kind: EdgeKind::Taken }, Edge { to: 4, kind: EdgeKind::NotTaken }],
+                },
+                BasicBlock { start: 4, end: 9, edges: vec![Edge { to: 0, kind: EdgeKind::Always }] },
+                BasicBlock { start: 9, end: 12, edges: vec![Edge { to: 0, kind: EdgeKind::Always }] },
+            ]
+        );
+        assert_eq!(cfg.block_of(6).map(|b| b.start), Some(4));
+
+        let expected = r#"digraph "hi.bin" {
+    node [shape=box fontname=monospace];
+    L_00 [label="L_00:\l000: MOV %R0, %BA\l001: ADD %R0, %BB\l002: CMP %R0, $0\l003: BNE $9\l"];
+    L_04 [label="L_04:\l004: MOV %D0, $0\l005: MOV %D1, $0\l006: MOV %D2, $0\l007: MOV %D3, $0\l008: BR  $0\l"];
+    L_09 [label="L_09:\l009: MOV %D1, $6\l010: MOV %D2, $116\l011: BR  $0\l"];
+    L_00 -> L_09 [color=darkgreen];
+    L_00 -> L_04 [color=red style=dashed];
+    L_04 -> L_00;
+    L_09 -> L_00;
+}
+"#;
+        assert_eq!(cfg.to_dot(&program), expected);
+    }
+
+    #[test]
+    fn test_end() {
+        // MOV %R0, $1 ; CMP %R0, $1 ; BE 3
+        let program = Program::new("", &[0x61, 0x00, 0x01, 0x41, 0x00, 0x01, 0x52, 0x03, 0x00]).expect("failed to load program");
+        let cfg = Cfg::new(&program);
+        assert_eq!(cfg.blocks().len(), 1);
+        let dot = cfg.to_dot(&program);
+        assert!(dot.contains("    end [shape=doublecircle];\n"), "{dot}");
+        assert!(dot.contains("    L_00 -> end [color=darkgreen];\n    L_00 -> end [color=red style=dashed];\n"), "{dot}");
+    }
+
+    #[test]
+    fn test_call() {
+        let source = r#"
+        CALL sub
+        HLT
+sub:    MOV  %R0, $1
+        RET
+"#;
+        let bytes = assemble_to_bytes(source, Isa::Extended).expect("failed to assemble");
+        let program = Program::with_isa("", &bytes, Isa::Extended).expect("failed to load program");
+        let cfg = Cfg::new(&program);
+        assert_eq!(
+            cfg.blocks(),
+            [
+                BasicBlock {
+                    start: 0,
+                    end: 1,
+                    edges: vec![Edge { to: 2, kind: EdgeKind::Call }, Edge { to: 1, kind: EdgeKind::Always }],
+                },
+                BasicBlock { start: 1, end: 2, edges: vec![] },
+                BasicBlock { start: 2, end: 4, edges: vec![] },
+            ]
+        );
+    }
+}
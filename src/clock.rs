@@ -0,0 +1,123 @@
+//! Paces emulation at a fixed rate of emulated instructions per second.
+//!
+//! Instead of sleeping a fixed time per instruction the clock tracks how many
+//! instructions should have run since it started and hands out that many, so
+//! a slow frame or a late wake up is caught up on the next call. If the host
+//! falls too far behind the drift is dropped rather than run in one burst.
+
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Debug)]
+pub struct Clock {
+    /// Emulated instructions per second at normal speed
+    hz: u32,
+    /// Multiplier on `hz`, > 1 for turbo and < 1 for slow motion
+    speed: f64,
+    /// Time and instruction count the current rate started at
+    base: Instant,
+    base_cycles: u64,
+    /// Instructions handed out so far
+    cycles: u64,
+}
+
+impl Clock {
+    /// Emulated instructions per second unless configured otherwise
+    pub const DEFAULT_HZ: u32 = 10_000;
+    /// Longest time to sleep between checks, keeps the rate smooth at low speeds
+    pub const MAX_SLEEP: Duration = Duration::from_millis(1);
+    /// Most drift caught up at once
+    const MAX_CATCH_UP: Duration = Duration::from_millis(100);
+    pub const MIN_SPEED: f64 = 1.0 / 64.0;
+    pub const MAX_SPEED: f64 = 64.0;
+
+    pub fn new(hz: u32) -> Self {
+        Self {
+            hz: hz.max(1),
+            speed: 1.0,
+            base: Instant::now(),
+            base_cycles: 0,
+            cycles: 0,
+        }
+    }
+
+    pub fn hz(&self) -> u32 {
+        self.hz
+    }
+
+    pub fn speed(&self) -> f64 {
+        self.speed
+    }
+
+    /// Changes the speed multiplier, clamped to `MIN_SPEED..=MAX_SPEED`
+    pub fn set_speed(&mut self, speed: f64) {
+        self.rebase(Instant::now());
+        self.speed = speed.clamp(Self::MIN_SPEED, Self::MAX_SPEED);
+    }
+
+    /// Time one instruction takes at the current speed
+    pub fn period(&self) -> Duration {
+        Duration::from_secs_f64(1.0 / self.rate())
+    }
+
+    /// Returns how many instructions are due to run at `now` and counts them as run
+    pub fn due(&mut self, now: Instant) -> u64 {
+        let elapsed = now.saturating_duration_since(self.base).as_secs_f64();
+        let target = self.base_cycles + (elapsed * self.rate()) as u64;
+        let mut due = target.saturating_sub(self.cycles);
+
+        let max = ((Self::MAX_CATCH_UP.as_secs_f64() * self.rate()) as u64).max(1);
+        if due > max {
+            due = max;
+            self.cycles += due;
+            self.rebase(now);
+        } else {
+            self.cycles += due;
+        }
+        due
+    }
+
+    fn rate(&self) -> f64 {
+        self.hz as f64 * self.speed
+    }
+
+    fn rebase(&mut self, now: Instant) {
+        self.base = now;
+        self.base_cycles = self.cycles;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::Clock;
+
+    #[test]
+    fn test_due() {
+        let mut clock = Clock::new(1000);
+        let start = clock.base;
+        assert_eq!(clock.due(start), 0);
+        assert_eq!(clock.due(start + Duration::from_millis(10)), 10);
+        assert_eq!(clock.due(start + Duration::from_millis(10)), 0);
+        // a late call catches up on everything missed
+        assert_eq!(clock.due(start + Duration::from_millis(50)), 40);
+    }
+
+    #[test]
+    fn test_drift_is_dropped() {
+        let mut clock = Clock::new(1000);
+        let start = clock.base;
+        assert_eq!(clock.due(start + Duration::from_secs(5)), 100);
+        assert_eq!(clock.due(start + Duration::from_secs(5) + Duration::from_millis(1)), 1);
+    }
+
+    #[test]
+    fn test_speed() {
+        let mut clock = Clock::new(1000);
+        clock.set_speed(4.0);
+        let start = clock.base;
+        assert_eq!(clock.due(start + Duration::from_millis(10)), 40);
+        clock.set_speed(1000.0);
+        assert_eq!(clock.speed(), Clock::MAX_SPEED);
+    }
+}
@@ -0,0 +1,192 @@
+//! Runs the table driven opcode cases in `spec/opcodes.txt`, the file
+//! documents its own format so other emulators can run the same cases.
+
+use strum::IntoEnumIterator;
+
+use crate::{
+    assembler::parse_value,
+    op_code::{Isa, OpCode},
+    registers::Register,
+    tgs::{Fault, MachineStatus, Tgs},
+};
+
+const SPEC: &str = include_str!("../spec/opcodes.txt");
+
+#[derive(Debug)]
+enum Expected {
+    Invalid,
+    /// State and status after the instruction
+    Executed(State, Outcome),
+}
+
+#[derive(Debug)]
+enum Outcome {
+    Running,
+    Halted,
+    /// Faulted at the instruction, which is at the setup PC
+    Faulted(fn(usize) -> Fault),
+}
+
+/// Register values, RAM bytes by address and the stack from the bottom
+#[derive(Debug, Default)]
+struct State {
+    registers: Vec<(Register, u8)>,
+    memory: Vec<(u8, u8)>,
+    stack: Option<Vec<u8>>,
+}
+
+const OUTCOMES: [&str; 3] = ["halted", "overflow", "underflow"];
+
+fn parse_outcome(field: &str) -> Outcome {
+    match field.split_whitespace().find(|w| OUTCOMES.contains(w)) {
+        Some("halted") => Outcome::Halted,
+        Some("overflow") => Outcome::Faulted(Fault::StackOverflow),
+        Some("underflow") => Outcome::Faulted(Fault::StackUnderflow),
+        _ => Outcome::Running,
+    }
+}
+
+#[derive(Debug)]
+struct Case {
+    line: usize,
+    isa: Isa,
+    bytes: [u8; 3],
+    setup: State,
+    expected: Expected,
+}
+
+fn parse_state(line: usize, field: &str) -> State {
+    let mut state = State::default();
+    for w in field.split_whitespace().filter(|w| !OUTCOMES.contains(w)) {
+        let (name, value) = w.split_once('=').unwrap_or_else(|| panic!("line {line}: expected register=value, got '{w}'"));
+        if name == "stack" {
+            let stack = value
+                .split(',')
+                .filter(|v| !v.is_empty())
+                .map(|v| parse_value(v).unwrap_or_else(|| panic!("line {line}: invalid value '{v}'")))
+                .collect();
+            state.stack = Some(stack);
+            continue;
+        }
+        let value = parse_value(value).unwrap_or_else(|| panic!("line {line}: invalid value '{value}'"));
+        match name.strip_prefix('[').and_then(|n| n.strip_suffix(']')) {
+            Some(addr) => {
+                let addr = parse_value(addr).unwrap_or_else(|| panic!("line {line}: invalid address '{addr}'"));
+                state.memory.push((addr, value));
+            }
+            None => {
+                let register = name.parse().unwrap_or_else(|_| panic!("line {line}: invalid register '{name}'"));
+                state.registers.push((register, value));
+            }
+        }
+    }
+    state
+}
+
+fn cases() -> Vec<Case> {
+    let mut isa = Isa::Classic;
+    SPEC.lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.split_once('#').map_or(line, |(code, _)| code)))
+        .filter(|(_, line)| !line.trim().is_empty())
+        .filter_map(|(line, code)| {
+            if let Some(name) = code.trim().strip_prefix("isa ") {
+                isa = clap::ValueEnum::from_str(name.trim(), true).unwrap_or_else(|_| panic!("line {line}: invalid isa '{name}'"));
+                return None;
+            }
+            let [bytes, setup, expected] = code.split(';').collect::<Vec<_>>()[..] else {
+                panic!("line {line}: expected '<bytes> ; <setup> ; <expected>'");
+            };
+            let bytes: Vec<u8> = bytes
+                .split_whitespace()
+                .map(|b| u8::from_str_radix(b, 16).unwrap_or_else(|_| panic!("line {line}: invalid byte '{b}'")))
+                .collect();
+            let bytes = bytes.try_into().unwrap_or_else(|_| panic!("line {line}: expected 3 bytes"));
+            let expected = match expected.trim() {
+                "invalid" => Expected::Invalid,
+                e => Expected::Executed(parse_state(line, e), parse_outcome(e)),
+            };
+            Some(Case { line, isa, bytes, setup: parse_state(line, setup), expected })
+        })
+        .collect()
+}
+
+fn apply(tgs: &mut Tgs, state: &State) {
+    tgs.restore(&state.registers);
+    for &(addr, value) in &state.memory {
+        tgs.ram_mut()[addr as usize] = value;
+    }
+    if let Some(stack) = &state.stack {
+        tgs.set_stack(stack);
+    }
+}
+
+#[test]
+fn test_spec() {
+    for case in cases() {
+        let Case { line, isa, bytes, setup, expected } = case;
+        let decoded = OpCode::decode(bytes, isa);
+        let (state, outcome) = match expected {
+            Expected::Invalid => {
+                assert!(decoded.is_err(), "line {line}: {bytes:02x?} decoded to {decoded:?}");
+                continue;
+            }
+            Expected::Executed(state, outcome) => (state, outcome),
+        };
+        let op_code = decoded.unwrap_or_else(|e| panic!("line {line}: {bytes:02x?} failed to decode: {e}"));
+        assert_eq!(op_code.encode(), bytes, "line {line}: {op_code} encodes differently");
+
+        let mut tgs = Tgs::with_isa(isa);
+        apply(&mut tgs, &setup);
+        let pc = tgs.address();
+        let mut expected = tgs.clone();
+        apply(&mut expected, &state);
+        tgs.process_instruction(op_code);
+        for r in Register::iter() {
+            assert_eq!(tgs.register(r), expected.register(r), "line {line}: {op_code}, register {r}");
+        }
+        assert_eq!(tgs.ram(), expected.ram(), "line {line}: {op_code}, RAM");
+        assert_eq!(tgs.stack(), expected.stack(), "line {line}: {op_code}, stack");
+        let status = match outcome {
+            Outcome::Running => MachineStatus::Running,
+            Outcome::Halted => MachineStatus::Halted,
+            Outcome::Faulted(fault) => MachineStatus::Faulted(fault(pc)),
+        };
+        assert_eq!(tgs.status(), status, "line {line}: {op_code}");
+    }
+}
+
+#[test]
+fn test_undocumented_rejected() {
+    let cases = cases();
+    // extended ISA cases add to the classic ones
+    let documented = |ins: u8, isa: Isa| {
+        cases.iter().any(|c| {
+            c.bytes[0] == ins && (c.isa == isa || c.isa == Isa::Classic) && !matches!(c.expected, Expected::Invalid)
+        })
+    };
+    for isa in [Isa::Classic, Isa::Extended] {
+        for ins in (0..=u8::MAX).filter(|&ins| !documented(ins, isa)) {
+            for operands in [[0x00, 0x00], [0x00, 0x01], [0x12, 0x10], [0xff, 0xff]] {
+                let bytes = [ins, operands[0], operands[1]];
+                assert!(OpCode::decode(bytes, isa).is_err(), "{bytes:02x?} has no {isa:?} case in the spec but decodes");
+            }
+        }
+
+        for addr in (0..=u8::MAX).filter(|&a| Register::try_from(a).is_err()) {
+            for bytes in [[0x10, addr, 0x00], [0x10, 0x00, addr], [0x11, addr, 0x00], [0x60, addr, 0x00]] {
+                assert!(OpCode::decode(bytes, isa).is_err(), "{bytes:02x?} uses an invalid register but decodes");
+            }
+        }
+    }
+    for bytes in [[0x80, 0x00, 0x08], [0x82, 0x08, 0x00], [0x83, 0x00, 0x08], [0x94, 0x08, 0x00], [0x96, 0x1a, 0x00]] {
+        assert!(OpCode::decode(bytes, Isa::Extended).is_err(), "{bytes:02x?} uses an invalid register but decodes");
+    }
+
+    for addr in Register::iter().filter(Register::is_extended).map(|r| r.get_addr()) {
+        for bytes in [[0x10, addr, 0x00], [0x10, 0x00, addr], [0x11, addr, 0x00], [0x40, 0x00, addr], [0x61, addr, 0x00]] {
+            assert!(OpCode::decode(bytes, Isa::Classic).is_err(), "{bytes:02x?} decodes with the classic ISA");
+            assert!(OpCode::decode(bytes, Isa::Extended).is_ok(), "{bytes:02x?} fails to decode with the extended ISA");
+        }
+    }
+}
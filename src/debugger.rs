@@ -0,0 +1,536 @@
+use std::{fmt::Display, num::Wrapping};
+
+use crate::{
+    breakpoints::{BreakReason, Breakpoints},
+    input::{Button, InputEvent, InputScript},
+    error::{Error, Result},
+    history::{Delta, History},
+    program::Program,
+    registers::Register,
+    snapshot::Snapshot,
+    tgs::{Fault, MachineStatus, Tgs},
+    trace::{TraceEntry, Tracer},
+};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RunState {
+    Running,
+    Paused,
+    /// Running until PC reaches the instruction index
+    RunTo(usize),
+}
+
+/// Why [`Debugger::run`] returned
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StopReason {
+    /// The instruction budget was used up
+    Budget,
+    /// The instruction count reached the end of the input script
+    EndOfInput,
+    ReachedPc(usize),
+    /// A HLT instruction stopped the machine
+    Halted,
+    Faulted(Fault),
+    Break(BreakReason),
+}
+
+impl Display for StopReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StopReason::Budget => write!(f, "instruction budget used up"),
+            StopReason::EndOfInput => write!(f, "end of input script"),
+            StopReason::ReachedPc(pc) => write!(f, "reached {pc:03}"),
+            StopReason::Halted => write!(f, "halted"),
+            StopReason::Faulted(fault) => write!(f, "faulted, {fault}"),
+            StopReason::Break(reason) => write!(f, "{reason}"),
+        }
+    }
+}
+
+/// Drives a [`Tgs`] through a [`Program`] with pause, step and run-to controls
+#[derive(Debug)]
+pub struct Debugger {
+    tgs: Tgs,
+    program: Program,
+    state: RunState,
+    breakpoints: Breakpoints,
+    input: Option<InputScript>,
+    /// Live button transitions recorded as an input script
+    recording: Option<InputScript>,
+    tracer: Option<Tracer>,
+    history: Option<History>,
+    last_break: Option<BreakReason>,
+    /// Set when resuming from a PC breakpoint so it does not fire again before PC moves on
+    skip_breakpoint: bool,
+}
+
+impl Debugger {
+    pub fn new(tgs: Tgs, program: Program) -> Self {
+        Self {
+            tgs,
+            program,
+            state: RunState::Running,
+            breakpoints: Breakpoints::new(),
+            input: None,
+            recording: None,
+            tracer: None,
+            history: None,
+            last_break: None,
+            skip_breakpoint: false,
+        }
+    }
+
+    pub fn tgs(&self) -> &Tgs {
+        &self.tgs
+    }
+
+    pub fn tgs_mut(&mut self) -> &mut Tgs {
+        &mut self.tgs
+    }
+
+    pub fn program(&self) -> &Program {
+        &self.program
+    }
+
+    pub fn state(&self) -> RunState {
+        self.state
+    }
+
+    /// Number of instructions executed so far
+    pub fn instructions(&self) -> u64 {
+        self.tgs.cycles()
+    }
+
+    pub fn breakpoints(&self) -> &Breakpoints {
+        &self.breakpoints
+    }
+
+    pub fn breakpoints_mut(&mut self) -> &mut Breakpoints {
+        &mut self.breakpoints
+    }
+
+    /// Drives BA and BB from the script instead of live input
+    pub fn set_input_script(&mut self, input: InputScript) {
+        self.input = Some(input);
+    }
+
+    pub fn has_input_script(&self) -> bool {
+        self.input.is_some()
+    }
+
+    /// Sets a button from live input, ignored while an input script drives the buttons
+    pub fn set_button(&mut self, button: Button, pressed: bool) {
+        if self.input.is_some() {
+            return;
+        }
+        let register = self.tgs.register_mut_ref(button.register());
+        if *register == Wrapping(pressed as u8) {
+            return;
+        }
+        *register = Wrapping(pressed as u8);
+        if let Some(recording) = &mut self.recording {
+            recording.push(InputEvent {
+                at: self.tgs.cycles(),
+                button,
+                pressed,
+            });
+        }
+    }
+
+    /// Writes a trace entry for every executed instruction from now on
+    pub fn set_tracer(&mut self, tracer: Tracer) {
+        self.tracer = Some(tracer);
+    }
+
+    pub fn flush_trace(&mut self) -> std::io::Result<()> {
+        self.tracer.as_mut().map_or(Ok(()), Tracer::flush)
+    }
+
+    /// Keeps the last `capacity` executed instructions so they can be stepped back over
+    pub fn enable_history(&mut self, capacity: usize) {
+        self.history = Some(History::new(capacity));
+    }
+
+    /// Number of instructions that can be stepped back over
+    pub fn history_len(&self) -> usize {
+        self.history.as_ref().map_or(0, History::len)
+    }
+
+    /// Records every live button transition from now on
+    pub fn start_recording(&mut self) {
+        self.recording = Some(InputScript::default());
+    }
+
+    /// Returns the recorded input script, ending at the current instruction count
+    pub fn finish_recording(&mut self) -> Option<InputScript> {
+        let mut recording = self.recording.take()?;
+        recording.set_end(self.instructions());
+        Some(recording)
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            program_name: self.program.get_name(),
+            program_hash: self.program.hash(),
+            instructions: self.instructions(),
+            registers: self.tgs.snapshot(),
+            memory: self.tgs.ram().to_vec(),
+            stack: self.tgs.stack().to_vec(),
+        }
+    }
+
+    /// Restores a snapshot taken from the same program
+    pub fn restore(&mut self, snapshot: &Snapshot) -> Result<()> {
+        snapshot.check_program(&self.program)?;
+        if snapshot.memory.len() != self.tgs.ram().len() {
+            return Err(Error::InvalidSnapshot(format!(
+                "snapshot has {} bytes of RAM but the machine has {}",
+                snapshot.memory.len(),
+                self.tgs.ram().len()
+            )));
+        }
+        self.tgs.restore(&snapshot.registers);
+        self.tgs.ram_mut().copy_from_slice(&snapshot.memory);
+        self.tgs.set_stack(&snapshot.stack);
+        self.tgs.set_cycles(snapshot.instructions);
+        self.tgs.set_status(MachineStatus::Running);
+        if let Some(input) = &mut self.input {
+            input.seek(snapshot.instructions);
+        }
+        // a recording continues from the restored state, like after stepping back
+        if let Some(recording) = &mut self.recording {
+            recording.truncate(snapshot.instructions);
+        }
+        if let Some(history) = &mut self.history {
+            history.clear();
+        }
+        Ok(())
+    }
+
+    fn input_ended(&self) -> bool {
+        self.input.as_ref().and_then(InputScript::end) == Some(self.instructions())
+    }
+
+    /// Reason execution was last paused by a breakpoint or watchpoint
+    pub fn last_break(&self) -> Option<&BreakReason> {
+        self.last_break.as_ref()
+    }
+
+    pub fn pc(&self) -> usize {
+        self.tgs.address()
+    }
+
+    pub fn pause(&mut self) {
+        self.state = RunState::Paused;
+    }
+
+    pub fn resume(&mut self) {
+        self.state = RunState::Running;
+        let pc = self.pc();
+        self.skip_breakpoint = matches!(self.last_break, Some(BreakReason::Breakpoint(at)) if at == pc);
+        self.last_break = None;
+    }
+
+    pub fn toggle_pause(&mut self) {
+        match self.state {
+            RunState::Paused => self.resume(),
+            RunState::Running | RunState::RunTo(_) => self.pause(),
+        }
+    }
+
+    /// Resumes until PC reaches `index`, then pauses
+    pub fn run_to(&mut self, index: usize) {
+        self.resume();
+        self.state = RunState::RunTo(index);
+    }
+
+    /// Executes the instruction at PC, returns false if the machine is halted or faulted.
+    /// PC breakpoints are ignored but watchpoints and conditions still pause execution.
+    pub fn step(&mut self) -> bool {
+        let registers = self.history.is_some().then(|| Delta::registers(&self.tgs));
+        if let Some(input) = &mut self.input {
+            input.apply(self.tgs.cycles(), &mut self.tgs);
+        }
+        let Some(instruction) = self.tgs.fetch(&self.program) else {
+            return false;
+        };
+        let delta = registers.map(|registers| Delta::new(instruction, registers, &self.tgs));
+        // cloning the machine on every step is only worth it when something compares against it
+        let before = (self.tracer.is_some() || self.breakpoints.watches_state()).then(|| self.tgs.clone());
+        self.tgs.process_instruction(instruction);
+        self.skip_breakpoint = false;
+        if let (Some(tracer), Some(before)) = (&mut self.tracer, &before) {
+            if let Err(e) = tracer.record(&TraceEntry::new(instruction, before, &self.tgs)) {
+                tracing::error!("Stopped tracing, failed to write trace: {e}");
+                self.tracer = None;
+            }
+        }
+        if let (Some(history), Some(delta)) = (&mut self.history, delta) {
+            history.push(delta);
+        }
+        if let Some(reason) = before.and_then(|before| self.breakpoints.check_after(&before, &self.tgs, &instruction)) {
+            self.break_on(reason);
+        }
+        true
+    }
+
+    /// Undoes the last executed instruction and pauses, returns false if the history is empty
+    pub fn step_back(&mut self) -> bool {
+        self.pause();
+        let Some(delta) = self.history.as_mut().and_then(History::pop) else {
+            return false;
+        };
+        self.undo(&delta);
+        true
+    }
+
+    /// Steps back until right before the last instruction that wrote `register` and pauses,
+    /// returns false and leaves the machine alone if no write is left in the history
+    pub fn reverse_continue(&mut self, register: Register) -> bool {
+        self.pause();
+        let writes = |delta: &Delta| delta.op_code.written_register() == Some(register);
+        if !self.history.as_ref().is_some_and(|history| history.iter().any(writes)) {
+            return false;
+        }
+        while let Some(delta) = self.history.as_mut().and_then(History::pop) {
+            self.undo(&delta);
+            if delta.op_code.written_register() == Some(register) {
+                return true;
+            }
+        }
+        false
+    }
+
+    fn undo(&mut self, delta: &Delta) {
+        delta.undo(&mut self.tgs);
+        if let Some(input) = &mut self.input {
+            input.seek(delta.cycles);
+        }
+        // a recording continues from the rewound state
+        if let Some(recording) = &mut self.recording {
+            recording.truncate(delta.cycles);
+        }
+    }
+
+    fn break_on(&mut self, reason: BreakReason) {
+        self.state = RunState::Paused;
+        self.last_break = Some(reason);
+    }
+
+    /// Runs at most `budget` instructions without pausing in between, stopping early when PC
+    /// reaches `until_pc`, falls off the end of the program or a breakpoint is hit. PC only
+    /// reaches `until_pc` by executing an instruction, so a run starting there goes around once
+    pub fn run(&mut self, budget: u64, until_pc: Option<usize>) -> StopReason {
+        self.resume();
+        for _ in 0..budget {
+            if self.input_ended() {
+                return StopReason::EndOfInput;
+            }
+            if !self.skip_breakpoint {
+                if let Some(reason) = self.breakpoints.check_before(&self.tgs) {
+                    self.break_on(reason.clone());
+                    return StopReason::Break(reason);
+                }
+            }
+            if !self.step() {
+                return self.stopped().unwrap_or(StopReason::Halted);
+            }
+            if let Some(reason) = &self.last_break {
+                return StopReason::Break(reason.clone());
+            }
+            if until_pc == Some(self.pc()) {
+                return StopReason::ReachedPc(self.pc());
+            }
+        }
+
+        if self.input_ended() {
+            StopReason::EndOfInput
+        } else if let Some(reason) = self.stopped() {
+            reason
+        } else {
+            StopReason::Budget
+        }
+    }
+
+    /// Why the machine stopped, None while it is running
+    fn stopped(&self) -> Option<StopReason> {
+        match self.tgs.status() {
+            MachineStatus::Running => None,
+            MachineStatus::Halted => Some(StopReason::Halted),
+            MachineStatus::Faulted(fault) => Some(StopReason::Faulted(fault)),
+        }
+    }
+
+    /// Advances execution by one instruction according to the run state
+    pub fn tick(&mut self) {
+        match self.state {
+            RunState::Paused => {}
+            RunState::Running | RunState::RunTo(_) => {
+                if self.input_ended() {
+                    self.pause();
+                    return;
+                }
+                if !self.skip_breakpoint {
+                    if let Some(reason) = self.breakpoints.check_before(&self.tgs) {
+                        self.break_on(reason);
+                        return;
+                    }
+                }
+                let stepped = self.step();
+                if !stepped || matches!(self.state, RunState::RunTo(index) if self.pc() == index) {
+                    self.pause();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        breakpoints::{BreakReason, WatchKind},
+        op_code::{Isa, OpCode},
+        program::Program,
+        registers::Register,
+        tgs::{Fault, MachineStatus, Tgs},
+    };
+
+    use super::{Debugger, RunState, StopReason};
+
+    fn debugger() -> Debugger {
+        let program = Program::new("hi.bin", include_bytes!("../assets/hi.bin")).expect("failed to load hi.bin");
+        Debugger::new(Tgs::new(), program)
+    }
+
+    #[test]
+    fn test_pause_and_step() {
+        let mut debugger = debugger();
+        debugger.pause();
+        debugger.tick();
+        assert_eq!(debugger.pc(), 0);
+
+        assert!(debugger.step());
+        assert_eq!(debugger.pc(), 1);
+        assert_eq!(debugger.state(), RunState::Paused);
+
+        debugger.resume();
+        debugger.tick();
+        assert_eq!(debugger.pc(), 2);
+    }
+
+    #[test]
+    fn test_run_to() {
+        let mut debugger = debugger();
+        *debugger.tgs_mut().register_mut_ref(Register::BA) = std::num::Wrapping(1);
+        debugger.run_to(10);
+        for _ in 0..100 {
+            debugger.tick();
+        }
+        assert_eq!(debugger.pc(), 10);
+        assert_eq!(debugger.state(), RunState::Paused);
+        assert_eq!(debugger.tgs().register(Register::D1).0, 6);
+    }
+
+    #[test]
+    fn test_breakpoints() {
+        let mut debugger = debugger();
+        debugger.breakpoints_mut().add_pc(2);
+        debugger.breakpoints_mut().add_watchpoint(Register::D3, WatchKind::Write);
+        for _ in 0..10 {
+            debugger.tick();
+        }
+        assert_eq!(debugger.pc(), 2);
+        assert_eq!(debugger.last_break(), Some(&BreakReason::Breakpoint(2)));
+
+        debugger.resume();
+        for _ in 0..10 {
+            debugger.tick();
+        }
+        assert_eq!(debugger.pc(), 8);
+        assert_eq!(debugger.last_break(), Some(&BreakReason::Written(Register::D3, 0)));
+    }
+
+    #[test]
+    fn test_entry_breakpoint() {
+        // a breakpoint on the entry PC fires before the first instruction, then lets it run
+        let mut debugger = debugger();
+        debugger.breakpoints_mut().add_pc(0);
+        assert_eq!(debugger.run(100, None), StopReason::Break(BreakReason::Breakpoint(0)));
+        assert_eq!(debugger.instructions(), 0);
+        assert_eq!(debugger.run(1, None), StopReason::Budget);
+        assert_eq!(debugger.instructions(), 1);
+    }
+
+    #[test]
+    fn test_run() {
+        let mut debugger = debugger();
+        assert_eq!(debugger.run(100, Some(8)), StopReason::ReachedPc(8));
+        assert_eq!(debugger.instructions(), 8);
+        assert_eq!(debugger.run(5, None), StopReason::Budget);
+        assert_eq!(debugger.instructions(), 13);
+
+        // a run starting at until_pc executes until PC gets back there
+        let program = Program::new("", &OpCode::BR(0).encode()).expect("failed to load program");
+        let mut looping = Debugger::new(Tgs::new(), program);
+        assert_eq!(looping.run(100, Some(0)), StopReason::ReachedPc(0));
+        assert_eq!(looping.instructions(), 1);
+        assert_eq!(looping.run(0, Some(0)), StopReason::Budget);
+
+        debugger.breakpoints_mut().add_condition("R0 == 1".parse().expect("failed to parse"));
+        *debugger.tgs_mut().register_mut_ref(Register::BA) = std::num::Wrapping(1);
+        assert!(matches!(debugger.run(100, None), StopReason::Break(_)));
+        assert_eq!(debugger.pc(), 1);
+
+        let program = Program::new("", &[0x61, 0x00, 0x01]).expect("failed to load program");
+        let mut debugger = Debugger::new(Tgs::new(), program);
+        assert_eq!(debugger.run(100, None), StopReason::Faulted(Fault::EndOfProgram(1)));
+        assert_eq!(debugger.instructions(), 1);
+
+        let (program, _) = Program::new_lenient("", &[0x61, 0x00, 0x01, 0x57, 0x00, 0x00, 0x61, 0x00, 0x02], Isa::Classic);
+        let mut debugger = Debugger::new(Tgs::new(), program);
+        assert_eq!(debugger.run(100, None), StopReason::Faulted(Fault::InvalidInstruction(1)));
+        assert_eq!(debugger.instructions(), 1);
+        assert!(!debugger.step());
+
+        // MOV %R0, $1 ; HLT ; MOV %R0, $2
+        let program = Program::new("", &[0x61, 0x00, 0x01, 0x00, 0x00, 0x00, 0x61, 0x00, 0x02]).expect("failed to load program");
+        let mut debugger = Debugger::new(Tgs::new(), program);
+        debugger.enable_history(10);
+        assert_eq!(debugger.run(100, None), StopReason::Halted);
+        assert_eq!((debugger.pc(), debugger.instructions()), (1, 2));
+        assert_eq!(debugger.tgs().status(), MachineStatus::Halted);
+        assert!(debugger.step_back());
+        assert_eq!(debugger.tgs().status(), MachineStatus::Running);
+    }
+
+    #[test]
+    fn test_step_back() {
+        let mut debugger = debugger();
+        debugger.enable_history(100);
+        debugger.set_input_script("at 20 press A for 5".parse().expect("failed to parse"));
+        let mut states = vec![];
+        for _ in 0..40 {
+            states.push(debugger.tgs().clone());
+            debugger.step();
+        }
+        let end = debugger.tgs().clone();
+
+        for expected in states.iter().rev().take(30) {
+            assert!(debugger.step_back());
+            assert_eq!(debugger.tgs(), expected);
+        }
+        for _ in 0..30 {
+            debugger.step();
+        }
+        assert_eq!(debugger.tgs(), &end);
+
+        assert!(debugger.reverse_continue(Register::D1));
+        assert_eq!(debugger.pc(), 5);
+        assert_eq!(debugger.tgs().register(Register::D1).0, 0);
+        // a miss leaves the machine and the history where they were
+        let (before, history_len) = (debugger.tgs().clone(), debugger.history_len());
+        assert!(!debugger.reverse_continue(Register::R7));
+        assert_eq!(debugger.tgs(), &before);
+        assert_eq!(debugger.history_len(), history_len);
+    }
+}
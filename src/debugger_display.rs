@@ -0,0 +1,107 @@
+use ratatui::{
+    buffer::Buffer,
+    layout::Rect,
+    style::{Style, Stylize},
+    text::Line,
+    widgets::{Block, Paragraph, Widget},
+};
+
+use crate::{breakpoints::Breakpoints, program::Program, registers::Register, tgs::Tgs};
+
+/// Panel listing every register of a [`Tgs`] in hex, decimal and binary
+pub struct RegistersView<'a> {
+    tgs: &'a Tgs,
+    selected: Option<Register>,
+}
+
+impl<'a> RegistersView<'a> {
+    pub fn new(tgs: &'a Tgs) -> Self {
+        Self { tgs, selected: None }
+    }
+
+    /// Highlights a register
+    pub fn selected(mut self, register: Register) -> Self {
+        self.selected = Some(register);
+        self
+    }
+}
+
+impl Widget for RegistersView<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let lines: Vec<Line> = self
+            .tgs
+            .registers()
+            .map(|r| {
+                let v = self.tgs.register(r).0;
+                let line = Line::from(format!("{:<2} {v:02X} {v:>3} {v:08b}", r.as_ref()));
+                if self.selected == Some(r) {
+                    line.black().on_white()
+                } else {
+                    line
+                }
+            })
+            .collect();
+        Paragraph::new(lines)
+            .block(Block::bordered().title("Registers"))
+            .white()
+            .on_black()
+            .render(area, buf);
+    }
+}
+
+/// Panel listing the program with the instruction at PC highlighted, a cursor and breakpoints
+pub struct DisassemblyView<'a> {
+    program: &'a Program,
+    breakpoints: Option<&'a Breakpoints>,
+    pc: usize,
+    cursor: usize,
+    pc_style: Style,
+}
+
+impl<'a> DisassemblyView<'a> {
+    pub fn new(program: &'a Program, pc: usize, cursor: usize) -> Self {
+        Self {
+            program,
+            breakpoints: None,
+            pc,
+            cursor,
+            pc_style: Style::default().black().on_yellow(),
+        }
+    }
+
+    /// Marks the PC breakpoints with a `*`
+    pub fn breakpoints(mut self, breakpoints: &'a Breakpoints) -> Self {
+        self.breakpoints = Some(breakpoints);
+        self
+    }
+}
+
+impl Widget for DisassemblyView<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let height = area.height.saturating_sub(2) as usize;
+        let start = self.cursor.saturating_sub(height / 2);
+        let lines: Vec<Line> = self
+            .program
+            .op_codes()
+            .iter()
+            .enumerate()
+            .skip(start)
+            .take(height)
+            .map(|(i, op_code)| {
+                let marker = if i == self.cursor { '>' } else { ' ' };
+                let breakpoint = if self.breakpoints.is_some_and(|b| b.has_pc(i)) { '*' } else { ' ' };
+                let line = Line::from(format!("{marker}{breakpoint}{i:03}: {op_code}"));
+                if i == self.pc {
+                    line.style(self.pc_style)
+                } else {
+                    line
+                }
+            })
+            .collect();
+        Paragraph::new(lines)
+            .block(Block::bordered().title(self.program.get_name()))
+            .white()
+            .on_black()
+            .render(area, buf);
+    }
+}
@@ -0,0 +1,104 @@
+//! Disassembles a [`Program`] into source that can be fed back into the assembler.
+//!
+//! Every branch target gets a synthesized label (`L_09:` for instruction 9) and
+//! branches refer to it by name. Each instruction is annotated with its 0-based
+//! index and raw bytes, and each label with the indices of the branches to it.
+
+use std::collections::BTreeMap;
+
+use crate::{op_code::OpCode, program::Program};
+
+const INS_WIDTH: usize = 28;
+
+/// Disassembles the program with labels and cross-references
+pub fn disassemble(program: &Program) -> String {
+    let len = program.len();
+    let mut refs: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+    for (i, op_code) in program.op_codes().iter().enumerate() {
+        if let Some(target) = op_code.jump_target(i) {
+            refs.entry(target).or_default().push(i);
+        }
+    }
+
+    let mut out = format!("; {}, {} instructions\n", program.get_name(), len);
+    for (i, op_code) in program.op_codes().iter().enumerate() {
+        if let Some(from) = refs.get(&i) {
+            push_label(&mut out, i, from);
+        }
+
+        let ins = match op_code.jump_target(i) {
+            Some(target) if target <= len => with_label(op_code, target),
+            Some(_) => format!("{op_code} ; past end"),
+            None => op_code.to_string(),
+        };
+        let [b0, b1, b2] = op_code.encode();
+        out.push_str(&format!("    {ins:<INS_WIDTH$}; {i:03}: {b0:02x} {b1:02x} {b2:02x}\n"));
+    }
+    if let Some(from) = refs.get(&len) {
+        push_label(&mut out, len, from);
+    }
+
+    out
+}
+
+/// Name of the label synthesized for an instruction index
+pub fn label_name(index: usize) -> String {
+    format!("L_{index:02}")
+}
+
+fn push_label(out: &mut String, index: usize, from: &[usize]) {
+    let label = format!("{}:", label_name(index));
+    let from = from.iter().map(|i| format!("{i:03}")).collect::<Vec<_>>().join(", ");
+    out.push_str(&format!("{label:<width$}; <- {from}\n", width = INS_WIDTH + 4));
+}
+
+fn with_label(op_code: &OpCode, target: usize) -> String {
+    let ins = op_code.to_string();
+    let (mnemonic, _) = ins.split_once('$').unwrap_or((&ins, ""));
+    format!("{mnemonic}{}", label_name(target))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{assembler::assemble_to_bytes, op_code::Isa, program::Program};
+
+    use super::disassemble;
+
+    #[test]
+    fn test_disassemble() {
+        let program = Program::new("hi.bin", include_bytes!("../assets/hi.bin")).expect("failed to load hi.bin");
+        let expected = r#"; hi.bin, 12 instructions
+L_00:                           ; <- 008, 011
+    MOV %R0, %BA                ; 000: 60 00 10
+    ADD %R0, %BB                ; 001: 10 00 11
+    CMP %R0, $0                 ; 002: 41 00 00
+    BNE L_09                    ; 003: 54 09 00
+    MOV %D0, $0                 ; 004: 61 12 00
+    MOV %D1, $0                 ; 005: 61 13 00
+    MOV %D2, $0                 ; 006: 61 14 00
+    MOV %D3, $0                 ; 007: 61 15 00
+    BR  L_00                    ; 008: 50 00 00
+L_09:                           ; <- 003
+    MOV %D1, $6                 ; 009: 61 13 06
+    MOV %D2, $116               ; 010: 61 14 74
+    BR  L_00                    ; 011: 50 00 00
+"#;
+        assert_eq!(disassemble(&program), expected);
+    }
+
+    #[test]
+    fn test_round_trip_assets() {
+        let assets: [&[u8]; 4] = [
+            include_bytes!("../assets/hi.bin"),
+            include_bytes!("../assets/demo1.bin"),
+            include_bytes!("../assets/demo2.bin"),
+            // branches to the end of the program and past it
+            &[0x50, 0x02, 0x00, 0x54, 0x09, 0x00],
+        ];
+        for bytes in assets {
+            let program = Program::new("", bytes).expect("failed to load program");
+            let assembled = assemble_to_bytes(&disassemble(&program), Isa::Classic).expect("failed to assemble disassembly");
+            assert_eq!(assembled, bytes);
+        }
+    }
+}
@@ -11,8 +11,38 @@ pub enum Error {
     InvalidSrcValueOpCode(u8),
     #[error("The value {0:08b} is not a valid OpCode")]
     InvalidOpCode(u8),
-    #[error("The program is invalid")]
-    InvalidProgram,
+    #[error("The program is {0} bytes long, expected a non-zero multiple of 3")]
+    InvalidProgramLength(usize),
+    #[error("Instruction {index} at byte offset {offset:#06x} [{:02x} {:02x} {:02x}] is invalid", .bytes[0], .bytes[1], .bytes[2])]
+    InvalidInstruction {
+        index: usize,
+        offset: usize,
+        bytes: [u8; 3],
+        #[source]
+        source: Box<Error>,
+    },
+    #[error("{} trailing bytes at byte offset {offset:#06x} do not form an instruction", .bytes.len())]
+    TruncatedInstruction { offset: usize, bytes: Vec<u8> },
+    #[error("Instruction {index} branches to {target} which is past the end of the program at {len}")]
+    BranchOutOfRange { index: usize, target: usize, len: usize },
+    #[error("The program has {len} instructions but PC can only address {max} with this ISA")]
+    ProgramTooLong { len: usize, max: usize },
+    #[error("Line {line}: {msg}")]
+    InvalidAssembly { line: usize, msg: String },
+    #[error("Line {line}: '{name}' is not defined")]
+    UndefinedSymbol { line: usize, name: String },
+    #[error("Line {line}: '{name}' is already defined")]
+    DuplicateSymbol { line: usize, name: String },
+    #[error("Line {line}: label '{name}' is at instruction {index} which is outside the 256 instruction page of this line")]
+    LabelOutOfRange { line: usize, name: String, index: usize },
+    #[error("Invalid condition '{0}', expected e.g. 'R3 == 9'")]
+    InvalidCondition(String),
+    #[error("Input script line {line}: {msg}")]
+    InvalidInputScript { line: usize, msg: String },
+    #[error("Invalid snapshot: {0}")]
+    InvalidSnapshot(String),
+    #[error("The snapshot was taken from {snapshot} which does not match the loaded program {program}")]
+    SnapshotProgramMismatch { snapshot: String, program: String },
     #[error("IO Error: {0}")]
     IoFailure(#[from] std::io::Error),
 }
@@ -0,0 +1,97 @@
+//! Text and JSON reports of the machine state after a headless run.
+
+use serde_json::{json, Map, Value};
+
+use crate::{
+    debugger::{Debugger, StopReason},
+    op_code::Isa,
+    tgs_display::render_ascii,
+};
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+/// Renders the register file and display digits of the debugger in the given format
+pub fn report(debugger: &Debugger, stop: &StopReason, format: OutputFormat) -> String {
+    match format {
+        OutputFormat::Text => report_text(debugger, stop),
+        OutputFormat::Json => report_json(debugger, stop),
+    }
+}
+
+fn display_digits(debugger: &Debugger) -> Vec<u8> {
+    // D3 is the leftmost digit
+    debugger.tgs().tgs_display().iter().rev().map(|d| d.0).collect()
+}
+
+fn report_text(debugger: &Debugger, stop: &StopReason) -> String {
+    let mut out = format!(
+        "{}: {stop} after {} instructions\n",
+        debugger.program().get_name(),
+        debugger.instructions()
+    );
+    out.push_str(&format!("status: {}\n", debugger.tgs().status()));
+    for r in debugger.tgs().registers() {
+        let v = debugger.tgs().register(r).0;
+        out.push_str(&format!("{:<2} {v:02X} {v:>3} {v:08b}\n", r.as_ref()));
+    }
+    out.push_str(&render_ascii(&display_digits(debugger)));
+    out.push('\n');
+    out
+}
+
+fn report_json(debugger: &Debugger, stop: &StopReason) -> String {
+    let registers: Map<String, Value> = debugger
+        .tgs()
+        .registers()
+        .map(|r| (r.to_string(), debugger.tgs().register(r).0.into()))
+        .collect();
+    let mut report = json!({
+        "program": debugger.program().get_name(),
+        "stop": stop.to_string(),
+        "status": debugger.tgs().status().to_string(),
+        "instructions": debugger.instructions(),
+        "registers": registers,
+        "display": display_digits(debugger),
+    });
+    if debugger.tgs().isa() == Isa::Extended {
+        report["memory"] = debugger.tgs().ram().into();
+        report["stack"] = debugger.tgs().stack().into();
+    }
+    report.to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{debugger::Debugger, program::Program, registers::Register, tgs::Tgs};
+
+    use super::{report, OutputFormat};
+
+    #[test]
+    fn test_report() {
+        let program = Program::new("hi.bin", include_bytes!("../assets/hi.bin")).expect("failed to load hi.bin");
+        let mut debugger = Debugger::new(Tgs::new(), program);
+        *debugger.tgs_mut().register_mut_ref(Register::BA) = std::num::Wrapping(1);
+        let stop = debugger.run(100, Some(11));
+
+        let text = report(&debugger, &stop, OutputFormat::Text);
+        assert!(text.starts_with("hi.bin: reached 011 after 6 instructions\nstatus: Running\n"), "{text}");
+        assert!(text.contains("\nD1 06   6 00000110\n"), "{text}");
+        assert!(text.ends_with("\n\n    |_    |\n    | |   |\n"), "{text}");
+        assert!(!text.contains("\nPG ") && !text.contains("\nTM "), "{text}");
+
+        let json: serde_json::Value =
+            serde_json::from_str(&report(&debugger, &stop, OutputFormat::Json)).expect("invalid json");
+        assert_eq!(json["instructions"], 6);
+        assert_eq!(json["status"], "Running");
+        assert_eq!(json["registers"]["D2"], 116);
+        assert_eq!(json["display"], serde_json::json!([0, 116, 6, 0]));
+        assert!(json.get("memory").is_none(), "{json}");
+        assert!(json.get("stack").is_none(), "{json}");
+        assert!(json["registers"].get("PG").is_none() && json["registers"].get("TM").is_none(), "{json}");
+    }
+}
@@ -0,0 +1,199 @@
+//! Bounded history of executed instructions for stepping backwards.
+//!
+//! Each entry is captured before its instruction runs and holds everything the
+//! instruction can change inline: every register, the one RAM byte a store can
+//! write and the two stack entries a RET can pop. That is about 40 bytes per
+//! entry without any allocation, so stepping does not need a copy of the machine.
+
+use std::{collections::VecDeque, num::Wrapping};
+
+use strum::{EnumCount, IntoEnumIterator};
+
+use crate::{
+    op_code::OpCode,
+    registers::Register,
+    tgs::{MachineStatus, Tgs},
+};
+
+/// What executing one instruction can change, enough to undo it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Delta {
+    pub op_code: OpCode,
+    /// Cycle count before the instruction
+    pub cycles: u64,
+    /// Old value of every register, in [`Register`] order
+    pub registers: [u8; Register::COUNT],
+    /// Address and old value of the RAM byte a store writes
+    pub memory: Option<(u8, u8)>,
+    /// Stack depth before the instruction
+    pub stack_len: u8,
+    /// The two entries on top of the stack before the instruction, the top one last
+    pub stack_top: [u8; 2],
+}
+
+impl Delta {
+    /// Captures the state `op_code` is about to change. `registers` are the values from before
+    /// the input script set the buttons for this instruction, see [`Delta::registers`]
+    pub fn new(op_code: OpCode, registers: [u8; Register::COUNT], before: &Tgs) -> Self {
+        let address = match op_code {
+            OpCode::ST(t, _) => Some(before.register(t).0),
+            OpCode::STV(v, _) => Some(v),
+            _ => None,
+        };
+        let memory = address.and_then(|addr| before.ram().get(addr as usize).map(|&old| (addr, old)));
+        let stack = before.stack();
+        let mut stack_top = [0; 2];
+        let top = &stack[stack.len().saturating_sub(2)..];
+        stack_top[2 - top.len()..].copy_from_slice(top);
+        Self {
+            op_code,
+            cycles: before.cycles(),
+            registers,
+            memory,
+            stack_len: stack.len() as u8,
+            stack_top,
+        }
+    }
+
+    /// Value of every register, in [`Register`] order
+    pub fn registers(tgs: &Tgs) -> [u8; Register::COUNT] {
+        let mut registers = [0; Register::COUNT];
+        for (value, register) in registers.iter_mut().zip(Register::iter()) {
+            *value = tgs.register(register).0;
+        }
+        registers
+    }
+
+    /// Restores the state from before the instruction, which the machine was running
+    pub fn undo(&self, tgs: &mut Tgs) {
+        for (register, &value) in Register::iter().zip(&self.registers) {
+            *tgs.register_mut_ref(register) = Wrapping(value);
+        }
+        if let Some((addr, value)) = self.memory {
+            tgs.ram_mut()[addr as usize] = value;
+        }
+        // the instruction pushed on top of the old stack or popped its top entries
+        let len = self.stack_len as usize;
+        if tgs.stack().len() != len {
+            let kept = tgs.stack().len().min(len);
+            let mut stack = [0; Tgs::STACK_SIZE];
+            stack[..kept].copy_from_slice(&tgs.stack()[..kept]);
+            stack[kept..len].copy_from_slice(&self.stack_top[2 - (len - kept)..]);
+            tgs.set_stack(&stack[..len]);
+        }
+        tgs.set_cycles(self.cycles);
+        tgs.set_status(MachineStatus::Running);
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct History {
+    deltas: VecDeque<Delta>,
+    capacity: usize,
+}
+
+impl History {
+    /// Keeps at most `capacity` instructions, dropping the oldest
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            deltas: VecDeque::with_capacity(capacity.min(1 << 16)),
+            capacity,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.deltas.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.deltas.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.deltas.clear();
+    }
+
+    pub fn push(&mut self, delta: Delta) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.deltas.len() == self.capacity {
+            self.deltas.pop_front();
+        }
+        self.deltas.push_back(delta);
+    }
+
+    /// Iterates from the oldest to the most recent instruction
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &Delta> {
+        self.deltas.iter()
+    }
+
+    /// Removes the most recent instruction
+    pub fn pop(&mut self) -> Option<Delta> {
+        self.deltas.pop_back()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        op_code::{Isa, OpCode},
+        registers::Register,
+        tgs::Tgs,
+    };
+
+    use super::{Delta, History};
+
+    #[test]
+    fn test_undo() {
+        let mut history = History::new(2);
+        let mut tgs = Tgs::new();
+        let start = tgs.clone();
+        let mut states = vec![];
+        for op_code in [OpCode::MOVV(Register::R0, 3), OpCode::ADDV(Register::R0, 4), OpCode::CMPV(Register::R0, 7)] {
+            states.push(tgs.clone());
+            history.push(Delta::new(op_code, Delta::registers(&tgs), &tgs));
+            tgs.process_instruction(op_code);
+        }
+        assert_eq!(history.len(), 2);
+
+        for expected in states.iter().rev().take(2) {
+            history.pop().expect("history is empty").undo(&mut tgs);
+            assert_eq!(&tgs, expected);
+        }
+        assert!(history.pop().is_none());
+        assert_ne!(tgs, start);
+    }
+
+    #[test]
+    fn test_undo_store() {
+        let mut tgs = Tgs::with_isa(Isa::Extended);
+        tgs.ram_mut()[9] = 1;
+        let before = tgs.clone();
+        let delta = Delta::new(OpCode::STV(9, Register::R0), Delta::registers(&tgs), &tgs);
+        tgs.process_instruction(OpCode::STV(9, Register::R0));
+        assert_eq!(delta.memory, Some((9, 1)));
+        delta.undo(&mut tgs);
+        assert_eq!(tgs, before);
+
+        // pushes and pops, including a RET popping a whole return address
+        tgs.set_stack(&[7]);
+        for op_code in [
+            OpCode::CALL(5),
+            OpCode::PUSH(Register::R0),
+            OpCode::POP(Register::R1),
+            OpCode::RET,
+            OpCode::POP(Register::R2),
+        ] {
+            let before = tgs.clone();
+            let delta = Delta::new(op_code, Delta::registers(&tgs), &tgs);
+            tgs.process_instruction(op_code);
+            assert_ne!(tgs.stack(), before.stack(), "{op_code}");
+            let after = tgs.clone();
+            delta.undo(&mut tgs);
+            assert_eq!(tgs, before, "{op_code}");
+            tgs = after;
+        }
+        assert!(tgs.stack().is_empty());
+    }
+}
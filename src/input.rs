@@ -0,0 +1,267 @@
+//! Scripted button input, replayed into the BA and BB registers at fixed
+//! instruction counts so runs are reproducible.
+//!
+//! One event per line, `#` starts a comment:
+//!
+//! ```text
+//! # hold A while instructions 1200 to 1499 execute
+//! at 1200 press A for 300
+//! # hold B until it is released
+//! at 2000 press B
+//! at 2010 release B
+//! # stop the run
+//! at 3000 end
+//! ```
+//!
+//! An event `at N` is applied right before the instruction that follows the
+//! first N executed instructions. Recordings of live input are written in the
+//! same format, using `press`, `release` and `end` lines.
+
+use std::{fmt::Display, fs, num::Wrapping, path::Path, str::FromStr};
+
+use crate::{
+    error::{Error, Result},
+    registers::Register,
+    tgs::Tgs,
+};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Button {
+    A,
+    B,
+}
+
+impl Button {
+    pub fn register(&self) -> Register {
+        match self {
+            Button::A => Register::BA,
+            Button::B => Register::BB,
+        }
+    }
+}
+
+impl FromStr for Button {
+    type Err = ();
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "A" | "a" => Ok(Button::A),
+            "B" | "b" => Ok(Button::B),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Display for Button {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Button::A => write!(f, "A"),
+            Button::B => write!(f, "B"),
+        }
+    }
+}
+
+/// A button going down or up after `at` executed instructions
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct InputEvent {
+    pub at: u64,
+    pub button: Button,
+    pub pressed: bool,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct InputScript {
+    /// Sorted by `at`, events at the same instruction keep their script order
+    events: Vec<InputEvent>,
+    /// Index of the next event to apply
+    next: usize,
+    /// Instruction count the run stops at
+    end: Option<u64>,
+}
+
+impl InputScript {
+    pub fn from_path(path: &Path) -> Result<Self> {
+        fs::read_to_string(path)?.parse()
+    }
+
+    pub fn events(&self) -> &[InputEvent] {
+        &self.events
+    }
+
+    pub fn end(&self) -> Option<u64> {
+        self.end
+    }
+
+    pub fn set_end(&mut self, end: u64) {
+        self.end = Some(end);
+    }
+
+    /// Appends an event, which must not be earlier than the last one
+    pub fn push(&mut self, event: InputEvent) {
+        debug_assert!(self.events.last().is_none_or(|e| e.at <= event.at));
+        self.events.push(event);
+    }
+
+    /// Drops every event at or after `instructions`
+    pub fn truncate(&mut self, instructions: u64) {
+        self.seek(instructions);
+        self.events.truncate(self.next);
+    }
+
+    /// Skips to the first event due at or after `instructions`
+    pub fn seek(&mut self, instructions: u64) {
+        self.next = self.events.partition_point(|e| e.at < instructions);
+    }
+
+    /// Applies every event due after `instructions` executed instructions
+    pub fn apply(&mut self, instructions: u64, tgs: &mut Tgs) {
+        while let Some(event) = self.events.get(self.next).filter(|e| e.at <= instructions) {
+            *tgs.register_mut_ref(event.button.register()) = Wrapping(event.pressed as u8);
+            self.next += 1;
+        }
+    }
+}
+
+impl FromStr for InputScript {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut events = Vec::new();
+        let mut end = None;
+        for (i, line) in s.lines().enumerate() {
+            let invalid = |msg: &str| Error::InvalidInputScript { line: i + 1, msg: msg.into() };
+            let line = line.split_once('#').map_or(line, |(code, _)| code);
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match words.as_slice() {
+                [] => {}
+                ["at", at, "press", button, "for", duration] => {
+                    let at: u64 = at.parse().map_err(|_| invalid("expected an instruction count after 'at'"))?;
+                    let button: Button = button.parse().map_err(|_| invalid("expected button A or B"))?;
+                    let duration: u64 = duration.parse().map_err(|_| invalid("expected an instruction count after 'for'"))?;
+                    let release = at.checked_add(duration).ok_or_else(|| invalid("release is past the last instruction count"))?;
+                    events.push(InputEvent { at, button, pressed: true });
+                    events.push(InputEvent { at: release, button, pressed: false });
+                }
+                ["at", at, action @ ("press" | "release"), button] => {
+                    let at: u64 = at.parse().map_err(|_| invalid("expected an instruction count after 'at'"))?;
+                    let button: Button = button.parse().map_err(|_| invalid("expected button A or B"))?;
+                    events.push(InputEvent { at, button, pressed: *action == "press" });
+                }
+                ["at", at, "end"] => {
+                    end = Some(at.parse().map_err(|_| invalid("expected an instruction count after 'at'"))?);
+                }
+                _ => return Err(invalid("expected 'at <instruction> press <A|B> [for <instructions>]', 'at <instruction> release <A|B>' or 'at <instruction> end'")),
+            }
+        }
+        events.sort_by_key(|e| e.at);
+
+        Ok(Self { events, next: 0, end })
+    }
+}
+
+impl Display for InputScript {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for event in &self.events {
+            let action = if event.pressed { "press" } else { "release" };
+            writeln!(f, "at {} {action} {}", event.at, event.button)?;
+        }
+        if let Some(end) = self.end {
+            writeln!(f, "at {end} end")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        debugger::{Debugger, StopReason},
+        error::Error,
+        program::Program,
+        registers::Register,
+        tgs::Tgs,
+    };
+
+    use super::{Button, InputScript};
+
+    #[test]
+    fn test_parse() {
+        let script: InputScript = "# comment\nat 10 press A for 5\n\nat 12 press b for 1 # short".parse().expect("failed to parse");
+        let events: Vec<_> = script.events().iter().map(|e| (e.at, e.button.to_string(), e.pressed)).collect();
+        assert_eq!(
+            events,
+            [
+                (10, "A".into(), true),
+                (12, "B".into(), true),
+                (13, "B".into(), false),
+                (15, "A".into(), false),
+            ]
+        );
+
+        let script: InputScript = "at 3 press A\nat 5 release A\nat 9 end".parse().expect("failed to parse");
+        assert_eq!(script.end(), Some(9));
+        assert_eq!(script.to_string(), "at 3 press A\nat 5 release A\nat 9 end\n");
+
+        for s in ["at 10 press C for 5", "at x press A for 5", "press A", "at 1 hold A", "at end"] {
+            assert!(s.parse::<InputScript>().is_err(), "{s}");
+        }
+
+        let err = format!("# overflow\nat {} press A for 2", u64::MAX).parse::<InputScript>().expect_err("release overflows");
+        assert!(matches!(err, Error::InvalidInputScript { line: 2, .. }), "{err}");
+    }
+
+    #[test]
+    fn test_demo1_count() {
+        let program = Program::new("demo1.bin", include_bytes!("../assets/demo1.bin")).expect("failed to load demo1.bin");
+        let mut debugger = Debugger::new(Tgs::new(), program);
+        let script = "at 100 press A for 50\nat 300 press A for 50\nat 500 press A for 50\nat 700 press B for 50";
+        debugger.set_input_script(script.parse().expect("failed to parse"));
+
+        debugger.run(1000, None);
+        assert_eq!(debugger.tgs().register(Register::R0).0, 2);
+        assert_eq!(debugger.tgs().register(Register::D0).0, 0b0101_1011);
+    }
+
+    #[test]
+    fn test_record_and_replay() {
+        let program = Program::new("demo1.bin", include_bytes!("../assets/demo1.bin")).expect("failed to load demo1.bin");
+        let mut debugger = Debugger::new(Tgs::new(), program);
+        debugger.start_recording();
+        for (i, button, pressed) in [(80, Button::A, true), (90, Button::A, false), (300, Button::B, true), (301, Button::B, false)] {
+            while debugger.instructions() < i {
+                debugger.tick();
+            }
+            debugger.set_button(button, pressed);
+            // repeated key events while held are not transitions
+            debugger.set_button(button, pressed);
+        }
+        debugger.run(123, None);
+        let recording = debugger.finish_recording().expect("not recording").to_string();
+        assert_eq!(recording, "at 80 press A\nat 90 release A\nat 300 press B\nat 301 release B\nat 424 end\n");
+
+        let program = Program::new("demo1.bin", include_bytes!("../assets/demo1.bin")).expect("failed to load demo1.bin");
+        let mut replay = Debugger::new(Tgs::new(), program);
+        replay.set_input_script(recording.parse().expect("failed to parse"));
+        assert_eq!(replay.run(u64::MAX, None), StopReason::EndOfInput);
+        assert_eq!(replay.instructions(), 424);
+        assert_eq!(replay.tgs(), debugger.tgs());
+    }
+
+    #[test]
+    fn test_record_and_restore() {
+        let program = Program::new("demo1.bin", include_bytes!("../assets/demo1.bin")).expect("failed to load demo1.bin");
+        let mut debugger = Debugger::new(Tgs::new(), program);
+        debugger.start_recording();
+        debugger.run(100, None);
+        let snapshot = debugger.snapshot();
+        debugger.run(50, None);
+        debugger.set_button(Button::A, true);
+
+        // presses after the restored instruction count never happened
+        debugger.restore(&snapshot).expect("failed to restore");
+        debugger.run(20, None);
+        debugger.set_button(Button::B, true);
+        let recording = debugger.finish_recording().expect("not recording").to_string();
+        assert_eq!(recording, "at 120 press B\nat 120 end\n");
+    }
+}
@@ -1,6 +1,21 @@
+pub mod assembler;
+pub mod breakpoints;
+pub mod cfg;
+pub mod clock;
+#[cfg(test)]
+mod conformance;
+pub mod debugger;
+pub mod debugger_display;
+pub mod disassembler;
+pub mod headless;
+pub mod history;
+pub mod input;
+pub mod lint;
 pub mod error;
 pub mod op_code;
 pub mod registers;
 pub mod tgs;
 pub mod tgs_display;
 pub mod program;
+pub mod snapshot;
+pub mod trace;
@@ -0,0 +1,173 @@
+//! Static checks over the instructions of a [`Program`].
+//!
+//! Every warning points at the 0-based index of the instruction it is about,
+//! the same index `tgs disasm` prints.
+
+use std::fmt::Display;
+
+use crate::{cfg::successors, op_code::OpCode, program::Program, registers::Register};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Lint {
+    /// A branch to an index past the end of the program
+    BranchOutOfRange(usize),
+    /// This and the following instructions can never be reached from instruction 0
+    Unreachable(usize),
+    /// A write to a register programs only read by convention
+    ReadOnlyWrite(Register),
+    /// A conditional branch that can be reached without a CMP setting CR first
+    BranchWithoutCmp,
+    /// A shift by a constant of 8 or more, which always yields zero
+    ShiftTooFar(u8),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Warning {
+    pub index: usize,
+    pub lint: Lint,
+}
+
+impl Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:03}: ", self.index)?;
+        match &self.lint {
+            Lint::BranchOutOfRange(target) => write!(f, "branch to {target} which is past the end of the program"),
+            Lint::Unreachable(1) => write!(f, "unreachable instruction"),
+            Lint::Unreachable(count) => write!(f, "{count} unreachable instructions"),
+            Lint::ReadOnlyWrite(register) => write!(f, "write to {register} which is read only by convention"),
+            Lint::BranchWithoutCmp => write!(f, "conditional branch can be reached without a CMP setting CR"),
+            Lint::ShiftTooFar(amount) => write!(f, "shift by {amount} always yields zero"),
+        }
+    }
+}
+
+/// Returns every warning for the program, sorted by instruction index
+pub fn lint(program: &Program) -> Vec<Warning> {
+    let op_codes = program.op_codes();
+    let mut warnings = Vec::new();
+
+    for (index, op_code) in op_codes.iter().enumerate() {
+        let mut warn = |lint| warnings.push(Warning { index, lint });
+        if let Some(target) = op_code.jump_target(index).filter(|&t| t >= op_codes.len()) {
+            warn(Lint::BranchOutOfRange(target));
+        }
+        match *op_code {
+            OpCode::CMP(_, _) | OpCode::CMPV(_, _) => {}
+            _ => {
+                if let Some(register @ (Register::BA | Register::BB | Register::PC | Register::CR | Register::PG)) =
+                    op_code.written_register()
+                {
+                    warn(Lint::ReadOnlyWrite(register));
+                }
+            }
+        }
+        if let OpCode::LSHV(_, amount @ 8..) | OpCode::RSHV(_, amount @ 8..) = *op_code {
+            warn(Lint::ShiftTooFar(amount));
+        }
+    }
+
+    let cr_set = cr_set(op_codes);
+    for (index, op_code) in op_codes.iter().enumerate() {
+        if op_code.is_conditional() && cr_set[index] == Some(false) {
+            warnings.push(Warning { index, lint: Lint::BranchWithoutCmp });
+        }
+    }
+
+    let mut index = 0;
+    while index < op_codes.len() {
+        if cr_set[index].is_none() {
+            let count = cr_set[index..].iter().take_while(|s| s.is_none()).count();
+            warnings.push(Warning { index, lint: Lint::Unreachable(count) });
+            index += count;
+        } else {
+            index += 1;
+        }
+    }
+
+    warnings.sort_by_key(|w| w.index);
+    warnings
+}
+
+/// For every instruction whether CR is set by a CMP, or another write to CR, on every path to
+/// it from instruction 0, None for unreachable instructions
+fn cr_set(op_codes: &[OpCode]) -> Vec<Option<bool>> {
+    let mut set = vec![None; op_codes.len()];
+    if op_codes.is_empty() {
+        return set;
+    }
+    set[0] = Some(false);
+    let mut work = vec![0];
+    while let Some(index) = work.pop() {
+        let out = set[index] == Some(true) || op_codes[index].written_register() == Some(Register::CR);
+        for next in successors(op_codes, index) {
+            let merged = set[next].map_or(out, |s| s && out);
+            if set[next] != Some(merged) {
+                set[next] = Some(merged);
+                work.push(next);
+            }
+        }
+    }
+    set
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{assembler::assemble_to_bytes, op_code::Isa, program::Program, registers::Register};
+
+    use super::{lint, Lint, Warning};
+
+    fn program(source: &str) -> Program {
+        let bytes = assemble_to_bytes(source, Isa::Classic).expect("failed to assemble");
+        Program::new("", &bytes).expect("failed to load program")
+    }
+
+    #[test]
+    fn test_lint() {
+        let source = r#"
+start:  BE   start      ; 0 no CMP yet
+        MOV  %BA, $1    ; 1
+        LSH  %R0, $8    ; 2
+        CMP  %R0, $0    ; 3
+loop:   BNE  loop       ; 4
+        MOV  %CR, $0    ; 5
+        BR   20         ; 6
+        MOV  %R1, $1    ; 7
+        MOV  %R1, $2    ; 8
+"#;
+        let warnings = lint(&program(source));
+        assert_eq!(
+            warnings,
+            [
+                Warning { index: 0, lint: Lint::BranchWithoutCmp },
+                Warning { index: 1, lint: Lint::ReadOnlyWrite(Register::BA) },
+                Warning { index: 2, lint: Lint::ShiftTooFar(8) },
+                Warning { index: 5, lint: Lint::ReadOnlyWrite(Register::CR) },
+                Warning { index: 6, lint: Lint::BranchOutOfRange(20) },
+                Warning { index: 7, lint: Lint::Unreachable(2) },
+            ]
+        );
+        assert_eq!(warnings[4].to_string(), "006: branch to 20 which is past the end of the program");
+
+        let warnings = lint(&program("BR 2\nHLT"));
+        assert_eq!(
+            warnings,
+            [
+                Warning { index: 0, lint: Lint::BranchOutOfRange(2) },
+                Warning { index: 1, lint: Lint::Unreachable(1) },
+            ]
+        );
+        assert_eq!(lint(&program("BR 1\nHLT")), []);
+    }
+
+    #[test]
+    fn test_assets_are_clean() {
+        for (name, bytes) in [
+            ("hi.bin", &include_bytes!("../assets/hi.bin")[..]),
+            ("demo1.bin", &include_bytes!("../assets/demo1.bin")[..]),
+            ("demo2.bin", &include_bytes!("../assets/demo2.bin")[..]),
+        ] {
+            let program = Program::new(name, bytes).expect("failed to load program");
+            assert_eq!(lint(&program), [], "{name}");
+        }
+    }
+}
@@ -1,27 +1,182 @@
-use std::{io::stdout, num::Wrapping, path::PathBuf, sync::{Arc, RwLock}};
+use std::{fs::File, io::{stdout, BufWriter}, path::{Path, PathBuf}, sync::{Arc, Mutex, RwLock}, time::Instant};
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use color_eyre::{eyre::eyre, Result};
 use ratatui::{
     backend::CrosstermBackend, crossterm::{
         event::{self, KeyCode, KeyEventKind},
         terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
         ExecutableCommand,
-    }, style::Stylize, widgets::Paragraph, Terminal
+    }, layout::{Constraint, Layout}, style::Stylize, widgets::Paragraph, Terminal
+};
+use tgs::{
+    assembler,
+    breakpoints::{Breakpoints, Condition, WatchKind},
+    cfg::Cfg,
+    clock::Clock,
+    debugger::Debugger,
+    debugger_display::{DisassemblyView, RegistersView},
+    disassembler,
+    error::Error,
+    headless::{self, OutputFormat},
+    input::{Button, InputScript},
+    lint,
+    op_code::Isa,
+    program::{self, Program},
+    registers::Register,
+    snapshot::Snapshot,
+    tgs::Tgs,
+    tgs_display::TgsDisplay,
+    trace::{PcRange, TraceFilter, TraceFormat, Tracer},
 };
-use tgs::{program::Program, registers::Register, tgs::Tgs, tgs_display::TgsDisplay};
 
 
 /// Simple program to greet a person
 #[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
+#[command(version, about, long_about = None, subcommand_negates_reqs = true)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
     /// binary to run
-    #[arg(short, long)]
-    bin: PathBuf,
+    #[arg(short, long, required = true)]
+    bin: Option<PathBuf>,
     /// print program to assembly
     #[arg(short, long)]
     print: bool,
+    /// start paused with the register and program panels shown
+    #[arg(short, long)]
+    debug: bool,
+    /// drive the 'a' and 'b' buttons from an input script or recording instead of the keyboard
+    #[arg(short, long, visible_alias = "replay", value_name = "FILE")]
+    input: Option<PathBuf>,
+    /// record the 'a' and 'b' button presses of the TUI session to replay them later
+    #[arg(short, long, value_name = "FILE", conflicts_with = "input")]
+    record: Option<PathBuf>,
+    /// load the binary even if some instructions are invalid, replacing them with traps
+    #[arg(long)]
+    lenient: bool,
+    /// instruction set to assemble or decode the binary with, extended adds RAM, a stack, PG paging
+    /// and the TM timer, see spec/opcodes.txt
+    #[arg(long, value_enum, default_value_t, global = true)]
+    isa: Isa,
+    /// emulated instructions per second
+    #[arg(long, default_value_t = Clock::DEFAULT_HZ)]
+    hz: u32,
+    /// start from a snapshot saved from the same program
+    #[arg(short, long, value_name = "FILE")]
+    snapshot: Option<PathBuf>,
+    #[command(flatten)]
+    breakpoints: BreakpointArgs,
+    #[command(flatten)]
+    headless: HeadlessArgs,
+    #[command(flatten)]
+    trace: TraceArgs,
+}
+
+#[derive(clap::Args, Debug)]
+struct TraceArgs {
+    /// write an execution trace to this file
+    #[arg(long, value_name = "FILE")]
+    trace: Option<PathBuf>,
+    /// format of the execution trace
+    #[arg(long, value_enum, default_value_t)]
+    trace_format: TraceFormat,
+    /// only trace instructions in this PC range, e.g. 10..20
+    #[arg(long, value_name = "RANGE")]
+    trace_pc: Option<PcRange>,
+    /// only trace instructions changing this register
+    #[arg(long, value_name = "REGISTER")]
+    trace_reg: Vec<Register>,
+}
+
+impl TraceArgs {
+    fn tracer(self) -> Result<Option<Tracer>> {
+        let Some(path) = self.trace else {
+            return Ok(None);
+        };
+        let writer = BufWriter::new(File::create(path)?);
+        let filter = TraceFilter {
+            pc: self.trace_pc.map(|r| r.0),
+            registers: self.trace_reg,
+        };
+        Ok(Some(Tracer::new(Box::new(writer), self.trace_format, filter)))
+    }
+}
+
+#[derive(clap::Args, Debug)]
+struct HeadlessArgs {
+    /// run without the TUI and print the final registers and display
+    #[arg(long)]
+    headless: bool,
+    /// maximum number of instructions to run headless
+    #[arg(long, default_value_t = 1_000_000)]
+    cycles: u64,
+    /// stop the headless run when an instruction leaves PC at this index
+    #[arg(long, value_name = "PC")]
+    until_pc: Option<usize>,
+    /// format of the headless output
+    #[arg(long, value_enum, default_value_t)]
+    format: OutputFormat,
+}
+
+#[derive(clap::Args, Debug)]
+struct BreakpointArgs {
+    /// pause before executing the instruction at this index
+    #[arg(long = "break", value_name = "PC")]
+    pc: Vec<usize>,
+    /// pause when a condition becomes true, e.g. "R3 == 9"
+    #[arg(long = "break-if", value_name = "CONDITION")]
+    conditions: Vec<Condition>,
+    /// pause when an instruction writes to the register
+    #[arg(long, value_name = "REGISTER")]
+    watch: Vec<Register>,
+    /// pause when the value of the register changes
+    #[arg(long, value_name = "REGISTER")]
+    watch_change: Vec<Register>,
+}
+
+impl BreakpointArgs {
+    fn apply(self, breakpoints: &mut Breakpoints) {
+        self.pc.into_iter().for_each(|pc| breakpoints.add_pc(pc));
+        self.conditions.into_iter().for_each(|c| breakpoints.add_condition(c));
+        self.watch.into_iter().for_each(|r| breakpoints.add_watchpoint(r, WatchKind::Write));
+        self.watch_change.into_iter().for_each(|r| breakpoints.add_watchpoint(r, WatchKind::Change));
+    }
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// assemble a program into a binary
+    Asm {
+        /// assembly source to read
+        source: PathBuf,
+        /// binary to write, defaults to the source with a .bin extension
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// disassemble a binary with labels and cross-references
+    Disasm {
+        /// binary to read
+        bin: PathBuf,
+    },
+    /// export the control-flow graph of a binary as Graphviz DOT
+    Cfg {
+        /// binary to read
+        bin: PathBuf,
+        /// DOT file to write, defaults to stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// warn about suspicious instructions in a binary
+    Lint {
+        /// binary to check
+        bin: PathBuf,
+    },
+    /// list every invalid instruction, trailing byte and out of range branch in a binary
+    Validate {
+        /// binary to check
+        bin: PathBuf,
+    },
 }
 
 fn main() -> Result<()> {
@@ -37,52 +192,156 @@ fn main() -> Result<()> {
 
     let args = Args::parse();
 
-    let tgs: Arc<RwLock<Tgs>> = Arc::new(RwLock::new(Tgs::new()));
     // let bin = &Path::new("assets/hi.bin");
     // let bin = &Path::new("assets/demo1.bin");
     // let bin = &Path::new("assets/demo2.bin");
-    let Args { bin, print } = args;
-    let program = Program::from_path(&bin)?;
+    let Args { command, bin, print, debug, input, record, lenient, isa, hz, snapshot, breakpoints, headless, trace } = args;
+    if let Some(command) = command {
+        return run_command(command, isa);
+    }
+    let bin = bin.ok_or_else(|| eyre!("--bin is required"))?;
+    let program = load_program(&bin, lenient, isa)?;
     if print {
         println!("{}", program.get_readable_program());
         return Ok(());
     }
-    
 
+    let mut tgs = Tgs::with_isa(program.isa());
+    tgs.set_hz(hz);
+    let mut debugger = Debugger::new(tgs, program);
+    breakpoints.apply(debugger.breakpoints_mut());
+    if let Some(input) = input {
+        debugger.set_input_script(InputScript::from_path(&input)?);
+    }
+    if let Some(snapshot) = snapshot {
+        debugger.restore(&Snapshot::from_path(&snapshot)?)?;
+    }
+    if let Some(tracer) = trace.tracer()? {
+        debugger.set_tracer(tracer);
+    }
+    if headless.headless {
+        let stop = debugger.run(headless.cycles, headless.until_pc);
+        debugger.flush_trace()?;
+        print!("{}", headless::report(&debugger, &stop, headless.format));
+        return Ok(());
+    }
+    if debug {
+        debugger.pause();
+        debugger.enable_history(HISTORY_CAPACITY);
+    }
+    if record.is_some() {
+        debugger.start_recording();
+    }
+
+    let debugger = Arc::new(RwLock::new(debugger));
+    run_tui(debugger.clone(), Clock::new(hz), &bin, debug)?;
+    debugger.write().expect("Failed to get lock").flush_trace()?;
+
+    if let Some(record) = record {
+        let mut debugger_write_locked = debugger.write().expect("Failed to get lock");
+        let name = debugger_write_locked.program().get_name();
+        if let Some(recording) = debugger_write_locked.finish_recording() {
+            std::fs::write(record, format!("# recorded from {name}\n{recording}"))?;
+        }
+    }
+    Ok(())
+}
+
+/// Loads a program, printing the bytes around invalid instructions
+fn load_program(bin: &Path, lenient: bool, isa: Isa) -> Result<Program> {
+    if lenient {
+        let (program, errors) = Program::from_path_lenient(bin, isa)?;
+        if !errors.is_empty() {
+            let bytes = std::fs::read(bin)?;
+            errors.iter().for_each(|e| print_problem(&bytes, e));
+        }
+        return Ok(program);
+    }
+    match Program::from_path(bin, isa) {
+        Err(e @ (Error::InvalidInstruction { offset, .. } | Error::TruncatedInstruction { offset, .. })) => {
+            eprint!("{}", program::hexdump(&std::fs::read(bin)?, offset));
+            Err(e.into())
+        }
+        program => Ok(program?),
+    }
+}
+
+/// Prints a problem found in a program with the bytes it is about
+fn print_problem(bytes: &[u8], e: &Error) {
+    eprintln!("{e}");
+    match e {
+        Error::InvalidInstruction { offset, source, .. } => {
+            eprintln!("  {source}");
+            eprint!("{}", program::hexdump(bytes, *offset));
+        }
+        Error::TruncatedInstruction { offset, .. } => eprint!("{}", program::hexdump(bytes, *offset)),
+        _ => {}
+    }
+}
+
+/// Instructions the debugger can step back over, about 10 MB of history and 26 seconds at the
+/// default `--hz`
+const HISTORY_CAPACITY: usize = 1 << 18;
+
+/// Path of a save state slot, next to the binary
+fn slot_path(bin: &Path, slot: u8) -> PathBuf {
+    bin.with_extension(format!("slot{slot}.tgss"))
+}
+
+fn run_tui(debugger: Arc<RwLock<Debugger>>, clock: Clock, bin: &Path, debug: bool) -> Result<()> {
     stdout().execute(EnterAlternateScreen)?;
     enable_raw_mode()?;
     let mut terminal = Terminal::new(CrosstermBackend::new(stdout()))?;
     terminal.clear()?;
 
-    let tgs1 = tgs.clone();
+    let clock = Arc::new(Mutex::new(clock));
+    let debugger1 = debugger.clone();
+    let clock1 = clock.clone();
     let _join_handle = std::thread::spawn(move || {
-        let tgs = tgs1;
+        let (debugger, clock) = (debugger1, clock1);
         loop {
-            let pc = {
-                let tgs_read_locked = tgs.read().expect("Failed to get lock");
-                tgs_read_locked.register(Register::PC).0 as usize
-            };
-            if let Some(instruction) = program.get_ins(pc) {
-                let mut tgs_write_locked = tgs.write().expect("Failed to get lock");
-                tgs_write_locked.process_instruction(*instruction);
+            let due = clock.lock().expect("Failed to get lock").due(Instant::now());
+            if due > 0 {
+                let mut debugger_write_locked = debugger.write().expect("Failed to get lock");
+                for _ in 0..due {
+                    debugger_write_locked.tick();
+                }
             }
-            std::thread::sleep(std::time::Duration::from_nanos(100000));
+            std::thread::sleep(Clock::MAX_SLEEP);
         }
     });
 
+    let mut cursor = 0;
+    let mut slot = 1;
+    let registers: Vec<Register> = debugger.read().expect("Failed to get lock").tgs().registers().collect();
+    let mut selected = 0;
+    let mut status = String::new();
     loop {
         terminal.draw(|frame| {
             let mut area = frame.area();
-            frame.render_widget(
-                Paragraph::new("TGS Emulator | 'q' to quit | 'a' and 'b' for controls")
-                    .white()
-                    .on_black(),
-                area,
-            );
+            let debugger_read_locked = debugger.read().expect("Failed to get lock");
+            let speed = clock.lock().expect("Failed to get lock").speed();
+            let speed = format!("{speed}x speed, '+' '-' '0'");
+            let title = if debug {
+                let state = match debugger_read_locked.last_break() {
+                    Some(reason) => format!("{:?} on {reason}", debugger_read_locked.state()),
+                    None => format!("{:?}", debugger_read_locked.state()),
+                };
+                format!(
+                    "TGS Emulator | 'q' to quit | 'a' and 'b' for controls | {state} | {speed} | space pause, 's' step, 'r' run to cursor, 'x' breakpoint | 'p' step back, '[' ']' register, 'w' back to last write | 1-9 slot {slot}, 'S' save, 'L' load"
+                )
+            } else {
+                format!("TGS Emulator | 'q' to quit | 'a' and 'b' for controls | {speed} | 1-9 slot {slot}, 'S' save, 'L' load")
+            };
+            frame.render_widget(Paragraph::new(title).white().on_black(), area);
+            let mut status_area = area;
+            status_area.y = 8;
+            status_area.height = 1;
+            let status = format!("{} | {status}", debugger_read_locked.tgs().status());
+            frame.render_widget(Paragraph::new(status).white().on_black(), status_area);
 
             area.y = 1;
-            let tgs_read_locked = tgs.read().expect("Failed to get lock");
-            for (i, v) in tgs_read_locked.tgs_display().iter_mut().rev().enumerate() {
+            for (i, v) in debugger_read_locked.tgs().tgs_display().iter_mut().rev().enumerate() {
                 area.x = 10*i as u16;
                 frame.render_stateful_widget(
                     TgsDisplay::new(),
@@ -90,27 +349,100 @@ fn main() -> Result<()> {
                     &mut v.0,
                 );
             }
+
+            if debug {
+                let mut area = frame.area();
+                area.y = 9;
+                area.height = area.height.saturating_sub(9);
+                let [registers_area, program_area] =
+                    Layout::horizontal([Constraint::Length(22), Constraint::Min(0)]).areas(area);
+                frame.render_widget(
+                    RegistersView::new(debugger_read_locked.tgs()).selected(registers[selected]),
+                    registers_area,
+                );
+                frame.render_widget(
+                    DisassemblyView::new(debugger_read_locked.program(), debugger_read_locked.pc(), cursor)
+                        .breakpoints(debugger_read_locked.breakpoints()),
+                    program_area,
+                );
+            }
         })?;
 
         if event::poll(std::time::Duration::from_millis(50))? {
             if let event::Event::Key(key) = event::read()? {
-                if key.kind == KeyEventKind::Press && key.code == KeyCode::Char('q') {
-                    break;
-                }
-
-                if key.kind == KeyEventKind::Press && key.code == KeyCode::Char('a') {
-                    let mut tgs_write_locked = tgs.write().expect("Failed to get lock");
-                    *tgs_write_locked.register_mut_ref(Register::BA) = Wrapping(1);
+                if key.kind != KeyEventKind::Press {
+                    continue;
                 }
-                if key.kind == KeyEventKind::Press && key.code == KeyCode::Char('b') {
-                    let mut tgs_write_locked = tgs.write().expect("Failed to get lock");
-                    *tgs_write_locked.register_mut_ref(Register::BB) = Wrapping(1);
+                let mut debugger_write_locked = debugger.write().expect("Failed to get lock");
+                match key.code {
+                    KeyCode::Char('q') => break,
+                    KeyCode::Char('a') => debugger_write_locked.set_button(Button::A, true),
+                    KeyCode::Char('b') => debugger_write_locked.set_button(Button::B, true),
+                    KeyCode::Char(c @ '1'..='9') => slot = c as u8 - b'0',
+                    KeyCode::Char(c @ ('+' | '-' | '0')) => {
+                        let mut clock = clock.lock().expect("Failed to get lock");
+                        let speed = match c {
+                            '+' => clock.speed() * 2.0,
+                            '-' => clock.speed() / 2.0,
+                            _ => 1.0,
+                        };
+                        clock.set_speed(speed);
+                    }
+                    KeyCode::Char('S') => {
+                        let path = slot_path(bin, slot);
+                        status = match debugger_write_locked.snapshot().save(&path) {
+                            Ok(()) => format!("Saved slot {slot} to {}", path.display()),
+                            Err(e) => format!("Failed to save slot {slot}: {e}"),
+                        };
+                    }
+                    KeyCode::Char('L') => {
+                        let path = slot_path(bin, slot);
+                        let snapshot = Snapshot::from_path(&path);
+                        status = match snapshot.and_then(|s| debugger_write_locked.restore(&s)) {
+                            Ok(()) => format!("Loaded slot {slot} from {}", path.display()),
+                            Err(e) => format!("Failed to load slot {slot}: {e}"),
+                        };
+                        cursor = debugger_write_locked.pc();
+                    }
+                    KeyCode::Char(' ') if debug => {
+                        debugger_write_locked.toggle_pause();
+                        cursor = debugger_write_locked.pc();
+                    }
+                    KeyCode::Char('s') if debug => {
+                        debugger_write_locked.pause();
+                        debugger_write_locked.step();
+                        cursor = debugger_write_locked.pc();
+                    }
+                    KeyCode::Char('p') if debug => {
+                        if !debugger_write_locked.step_back() {
+                            status = "Nothing to step back over".into();
+                        }
+                        cursor = debugger_write_locked.pc();
+                    }
+                    KeyCode::Char('[') if debug => selected = selected.checked_sub(1).unwrap_or(registers.len() - 1),
+                    KeyCode::Char(']') if debug => selected = (selected + 1) % registers.len(),
+                    KeyCode::Char('w') if debug => {
+                        let register = registers[selected];
+                        status = if debugger_write_locked.reverse_continue(register) {
+                            format!("Stepped back to the last write of {register}")
+                        } else {
+                            format!("No write of {register} in the history")
+                        };
+                        cursor = debugger_write_locked.pc();
+                    }
+                    KeyCode::Char('r') if debug => debugger_write_locked.run_to(cursor),
+                    KeyCode::Char('x') if debug => debugger_write_locked.breakpoints_mut().toggle_pc(cursor),
+                    KeyCode::Up | KeyCode::Char('k') if debug => cursor = cursor.saturating_sub(1),
+                    KeyCode::Down | KeyCode::Char('j') if debug => {
+                        cursor = (cursor + 1).min(debugger_write_locked.program().len().saturating_sub(1));
+                    }
+                    _ => {}
                 }
             }
         } else {
-            let mut tgs_write_locked = tgs.write().expect("Failed to get lock");
-            *tgs_write_locked.register_mut_ref(Register::BA) = Wrapping(0);
-            *tgs_write_locked.register_mut_ref(Register::BB) = Wrapping(0);
+            let mut debugger_write_locked = debugger.write().expect("Failed to get lock");
+            debugger_write_locked.set_button(Button::A, false);
+            debugger_write_locked.set_button(Button::B, false);
         }
     }
 
@@ -120,6 +452,48 @@ fn main() -> Result<()> {
     Ok(())
 }
 
+fn run_command(command: Command, isa: Isa) -> Result<()> {
+    match command {
+        Command::Asm { source, output } => {
+            let output = output.unwrap_or_else(|| source.with_extension("bin"));
+            let bytes = assembler::assemble_to_bytes(&std::fs::read_to_string(&source)?, isa)?;
+            std::fs::write(&output, bytes)?;
+        }
+        Command::Disasm { bin } => {
+            let program = load_program(&bin, false, isa)?;
+            print!("{}", disassembler::disassemble(&program));
+        }
+        Command::Cfg { bin, output } => {
+            let program = load_program(&bin, false, isa)?;
+            let dot = Cfg::new(&program).to_dot(&program);
+            match output {
+                Some(output) => std::fs::write(output, dot)?,
+                None => print!("{dot}"),
+            }
+        }
+        Command::Lint { bin } => {
+            let program = load_program(&bin, false, isa)?;
+            let warnings = lint::lint(&program);
+            for warning in &warnings {
+                println!("{}: {warning}", program.get_name());
+            }
+            if !warnings.is_empty() {
+                return Err(eyre!("found {} warnings in {}", warnings.len(), bin.display()));
+            }
+        }
+        Command::Validate { bin } => {
+            let bytes = std::fs::read(&bin)?;
+            let errors = Program::validate(&bytes, isa);
+            if !errors.is_empty() {
+                errors.iter().for_each(|e| print_problem(&bytes, e));
+                return Err(eyre!("found {} problems in {}", errors.len(), bin.display()));
+            }
+            println!("{}: {} valid instructions", bin.display(), bytes.len() / 3);
+        }
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod test {
     use hex::FromHex;
@@ -1,13 +1,38 @@
 use std::fmt::Display;
 
+use strum::IntoEnumIterator;
+
 use crate::{
     error::Error,
     registers::Register,
 };
 
-#[derive(Copy, Clone, Debug)]
+/// Instruction set a program is decoded with
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Isa {
+    /// The instructions of the DCC specification
+    #[default]
+    Classic,
+    /// Classic plus RAM, a stack and paged addressing
+    Extended,
+}
+
+impl Isa {
+    /// Number of instructions PC can address, a single page for the classic ISA
+    pub fn max_len(&self) -> usize {
+        match self {
+            Isa::Classic => 1 << 8,
+            Isa::Extended => 1 << 16,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 #[repr(u8)]
 pub enum OpCode {
+    /// Stops the machine, only encoded as all zero bytes
+    HLT = 0b0000_0000,
+
     ADD(Register, Register) = 0b0001_0000,
     ADDV(Register, u8) = 0b0001_0001,
     SUB(Register, Register) = 0b0001_0010,
@@ -31,9 +56,37 @@ pub enum OpCode {
     BNE(u8) = 0b0101_0100,
     BG(u8) = 0b0101_0110,
     BL(u8) = 0b0101_1000,
+    /// Branch if higher, unsigned greater than
+    BHI(u8) = 0b0101_1010,
+    /// Branch if lower, unsigned less than
+    BLO(u8) = 0b0101_1100,
 
     MOV(Register, Register) = 0b0110_0000,
     MOVV(Register, u8) = 0b0110_0001,
+
+    /// Loads the RAM byte at the address in the source register, extended ISA only
+    LD(Register, Register) = 0b1000_0000,
+    /// Loads the RAM byte at an address, extended ISA only
+    LDV(Register, u8) = 0b1000_0001,
+    /// Stores the source register at the address in the target register, extended ISA only
+    ST(Register, Register) = 0b1000_0010,
+    /// Stores the source register at an address, extended ISA only
+    STV(u8, Register) = 0b1000_0011,
+
+    /// Pushes the page and index of the next instruction and branches in the page, extended ISA only
+    CALL(u8) = 0b1001_0000,
+    /// Pops a page and instruction index into PG and PC, extended ISA only
+    RET = 0b1001_0010,
+    /// Pushes a register onto the stack, extended ISA only
+    PUSH(Register) = 0b1001_0100,
+    /// Pops the top of the stack into a register, extended ISA only
+    POP(Register) = 0b1001_0110,
+
+    /// Branches to an instruction in any page by setting PG and PC, extended ISA only
+    BRF(u16) = 0b1010_0000,
+
+    /// Marks bytes that failed to decode in a leniently loaded program, never decoded from bytes
+    TRAP([u8; 3]) = 0b1111_1111,
 }
 
 impl OpCode {
@@ -46,11 +99,158 @@ impl OpCode {
         const BRANCHING: u8 = 0b0101_0000;
         (byte & BRANCHING) == BRANCHING
     }
+
+    /// Returns the instruction index a branch op code jumps to
+    pub fn branch_target(&self) -> Option<u8> {
+        match *self {
+            OpCode::BR(v) | OpCode::BE(v) | OpCode::BNE(v) | OpCode::BG(v) | OpCode::BL(v)
+            | OpCode::BHI(v) | OpCode::BLO(v) | OpCode::CALL(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    /// Returns the instruction index a branch op code at `index` jumps to. Branches other than
+    /// BRF stay in the 256 instruction page of `index`
+    pub fn jump_target(&self, index: usize) -> Option<usize> {
+        match *self {
+            OpCode::BRF(target) => Some(target as usize),
+            _ => self.branch_target().map(|t| (index & !0xff) | t as usize),
+        }
+    }
+
+    /// Returns false if execution never continues with the next instruction, i.e. for BR and HLT.
+    /// CALL falls through as the subroutine returns to the next instruction
+    pub fn falls_through(&self) -> bool {
+        !matches!(self, OpCode::BR(_) | OpCode::BRF(_) | OpCode::RET | OpCode::HLT | OpCode::TRAP(_))
+    }
+
+    /// Returns true for branches taken depending on CR
+    pub fn is_conditional(&self) -> bool {
+        self.branch_target().is_some() && self.falls_through() && !matches!(self, OpCode::CALL(_))
+    }
+
+    /// Decodes the 3 byte form, extended instructions and registers are only accepted with
+    /// [`Isa::Extended`]
+    pub fn decode(bytes: [u8; 3], isa: Isa) -> Result<Self, Error> {
+        let [ins, target, src] = bytes;
+        if isa == Isa::Classic {
+            let op_code = Self::decode_classic(bytes)?;
+            if let Some(register) = Register::iter().find(|r| r.is_extended() && op_code.uses_register(*r)) {
+                return Err(Error::InvalidRegisterAddress(register.get_addr()));
+            }
+            return Ok(op_code);
+        }
+        let op_code = match ins {
+            0b1000_0000 => Self::LD(target.try_into()?, src.try_into()?),
+            0b1000_0001 => Self::LDV(target.try_into()?, src),
+            0b1000_0010 => Self::ST(target.try_into()?, src.try_into()?),
+            0b1000_0011 => Self::STV(target, src.try_into()?),
+            0b1001_0000 if src == 0 => Self::CALL(target),
+            0b1001_0010 if [target, src] == [0, 0] => Self::RET,
+            0b1001_0100 if src == 0 => Self::PUSH(target.try_into()?),
+            0b1001_0110 if src == 0 => Self::POP(target.try_into()?),
+            0b1001_0000 | 0b1001_0010 | 0b1001_0100 | 0b1001_0110 => return Err(Error::InvalidOpCode(ins)),
+            0b1010_0000 => Self::BRF(u16::from_be_bytes([target, src])),
+            _ => Self::decode_classic(bytes)?,
+        };
+        Ok(op_code)
+    }
+
+    /// Returns true if the op code reads or writes the register as an operand
+    pub fn uses_register(&self, register: Register) -> bool {
+        match *self {
+            OpCode::ADD(t, s) | OpCode::SUB(t, s) | OpCode::LSH(t, s) | OpCode::RSH(t, s)
+            | OpCode::AND(t, s) | OpCode::OR(t, s) | OpCode::XOR(t, s) | OpCode::CMP(t, s)
+            | OpCode::MOV(t, s) | OpCode::LD(t, s) | OpCode::ST(t, s) => t == register || s == register,
+            OpCode::ADDV(t, _) | OpCode::SUBV(t, _) | OpCode::LSHV(t, _) | OpCode::RSHV(t, _)
+            | OpCode::ANDV(t, _) | OpCode::ORV(t, _) | OpCode::XORV(t, _) | OpCode::CMPV(t, _)
+            | OpCode::MOVV(t, _) | OpCode::LDV(t, _) | OpCode::STV(_, t) | OpCode::PUSH(t)
+            | OpCode::POP(t) => t == register,
+            _ => false,
+        }
+    }
+
+    /// Returns the register the op code writes to, branches only write PC so return None
+    pub fn written_register(&self) -> Option<Register> {
+        match *self {
+            OpCode::ADD(t, _) | OpCode::SUB(t, _) | OpCode::LSH(t, _) | OpCode::RSH(t, _)
+            | OpCode::AND(t, _) | OpCode::OR(t, _) | OpCode::XOR(t, _) | OpCode::MOV(t, _)
+            | OpCode::ADDV(t, _) | OpCode::SUBV(t, _) | OpCode::LSHV(t, _) | OpCode::RSHV(t, _)
+            | OpCode::ANDV(t, _) | OpCode::ORV(t, _) | OpCode::XORV(t, _) | OpCode::MOVV(t, _)
+            | OpCode::LD(t, _) | OpCode::LDV(t, _) | OpCode::POP(t) => Some(t),
+            OpCode::CMP(_, _) | OpCode::CMPV(_, _) => Some(Register::CR),
+            OpCode::BR(_) | OpCode::BE(_) | OpCode::BNE(_) | OpCode::BG(_) | OpCode::BL(_)
+            | OpCode::BHI(_) | OpCode::BLO(_) | OpCode::BRF(_) => None,
+            OpCode::ST(_, _) | OpCode::STV(_, _) | OpCode::CALL(_) | OpCode::RET | OpCode::PUSH(_)
+            | OpCode::HLT | OpCode::TRAP(_) => None,
+        }
+    }
+
+    /// Encodes the op code into the 3 byte `[ins, target, src]` form read by `TryFrom<[u8; 3]>`.
+    /// The decoder ignores the third byte of branches, it is always encoded as 0
+    pub fn encode(&self) -> [u8; 3] {
+        match *self {
+            OpCode::HLT => [0b0000_0000, 0, 0],
+
+            OpCode::ADD(t, s) => [0b0001_0000, t.get_addr(), s.get_addr()],
+            OpCode::SUB(t, s) => [0b0001_0010, t.get_addr(), s.get_addr()],
+            OpCode::LSH(t, s) => [0b0010_0000, t.get_addr(), s.get_addr()],
+            OpCode::RSH(t, s) => [0b0010_0010, t.get_addr(), s.get_addr()],
+            OpCode::AND(t, s) => [0b0011_0000, t.get_addr(), s.get_addr()],
+            OpCode::OR(t, s)  => [0b0011_0010, t.get_addr(), s.get_addr()],
+            OpCode::XOR(t, s) => [0b0011_0100, t.get_addr(), s.get_addr()],
+            OpCode::CMP(t, s) => [0b0100_0000, t.get_addr(), s.get_addr()],
+            OpCode::MOV(t, s) => [0b0110_0000, t.get_addr(), s.get_addr()],
+
+            OpCode::ADDV(t, v) => [0b0001_0001, t.get_addr(), v],
+            OpCode::SUBV(t, v) => [0b0001_0011, t.get_addr(), v],
+            OpCode::LSHV(t, v) => [0b0010_0001, t.get_addr(), v],
+            OpCode::RSHV(t, v) => [0b0010_0011, t.get_addr(), v],
+            OpCode::ANDV(t, v) => [0b0011_0001, t.get_addr(), v],
+            OpCode::ORV(t, v)  => [0b0011_0011, t.get_addr(), v],
+            OpCode::XORV(t, v) => [0b0011_0101, t.get_addr(), v],
+            OpCode::CMPV(t, v) => [0b0100_0001, t.get_addr(), v],
+            OpCode::MOVV(t, v) => [0b0110_0001, t.get_addr(), v],
+
+            OpCode::BR(v)  => [0b0101_0000, v, 0],
+            OpCode::BE(v)  => [0b0101_0010, v, 0],
+            OpCode::BNE(v) => [0b0101_0100, v, 0],
+            OpCode::BG(v)  => [0b0101_0110, v, 0],
+            OpCode::BL(v)  => [0b0101_1000, v, 0],
+            OpCode::BHI(v) => [0b0101_1010, v, 0],
+            OpCode::BLO(v) => [0b0101_1100, v, 0],
+
+            OpCode::LD(t, s)  => [0b1000_0000, t.get_addr(), s.get_addr()],
+            OpCode::LDV(t, v) => [0b1000_0001, t.get_addr(), v],
+            OpCode::ST(t, s)  => [0b1000_0010, t.get_addr(), s.get_addr()],
+            OpCode::STV(v, s) => [0b1000_0011, v, s.get_addr()],
+
+            OpCode::CALL(v) => [0b1001_0000, v, 0],
+            OpCode::RET     => [0b1001_0010, 0, 0],
+            OpCode::PUSH(s) => [0b1001_0100, s.get_addr(), 0],
+            OpCode::POP(t)  => [0b1001_0110, t.get_addr(), 0],
+
+            OpCode::BRF(v) => {
+                let [hi, lo] = v.to_be_bytes();
+                [0b1010_0000, hi, lo]
+            }
+
+            OpCode::TRAP(bytes) => bytes,
+        }
+    }
+}
+
+impl From<OpCode> for [u8; 3] {
+    fn from(op_code: OpCode) -> Self {
+        op_code.encode()
+    }
 }
 
 impl Display for OpCode {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match *self {
+            OpCode::HLT => write!(f, "HLT"),
+
             OpCode::ADD(r1, r2) => write!(f, "ADD %{r1}, %{r2}"),
             OpCode::SUB(r1, r2) => write!(f, "SUB %{r1}, %{r2}"),
             OpCode::LSH(r1, r2) => write!(f, "LSH %{r1}, %{r2}"),
@@ -76,22 +276,50 @@ impl Display for OpCode {
             OpCode::BNE(v) => write!(f, "BNE ${v}"),
             OpCode::BG(v)  => write!(f, "BG  ${v}"),
             OpCode::BL(v)  => write!(f, "BL  ${v}"),
+            OpCode::BHI(v) => write!(f, "BHI ${v}"),
+            OpCode::BLO(v) => write!(f, "BLO ${v}"),
+
+            OpCode::LD(r1, r2) => write!(f, "LD  %{r1}, [%{r2}]"),
+            OpCode::LDV(r, v)  => write!(f, "LD  %{r}, [${v}]"),
+            OpCode::ST(r1, r2) => write!(f, "ST  [%{r1}], %{r2}"),
+            OpCode::STV(v, r)  => write!(f, "ST  [${v}], %{r}"),
+
+            OpCode::CALL(v) => write!(f, "CALL ${v}"),
+            OpCode::RET     => write!(f, "RET"),
+            OpCode::PUSH(r) => write!(f, "PUSH %{r}"),
+            OpCode::POP(r)  => write!(f, "POP %{r}"),
+
+            OpCode::BRF(v) => write!(f, "BRF ${v}"),
+
+            OpCode::TRAP([b0, b1, b2]) => write!(f, "TRAP [{b0:02x} {b1:02x} {b2:02x}]"),
         }
     }
 }
 
+/// Decodes with [`Isa::Classic`]
 impl TryFrom<[u8; 3]> for OpCode {
     type Error = Error;
 
-    fn try_from([ins, target, src]: [u8; 3]) -> std::result::Result<Self, Self::Error> {
-        let val = if Self::is_branching(ins) {
+    fn try_from(bytes: [u8; 3]) -> std::result::Result<Self, Self::Error> {
+        Self::decode(bytes, Isa::Classic)
+    }
+}
+
+impl OpCode {
+    /// Decodes the instructions of the classic ISA with any register
+    fn decode_classic([ins, target, src]: [u8; 3]) -> Result<Self, Error> {
+        let val = if [ins, target, src] == [0, 0, 0] {
+            OpCode::HLT
+        } else if Self::is_branching(ins) {
             match ins {
                 0b0101_0000 => OpCode::BR(target),
                 0b0101_0010 => OpCode::BE(target),
                 0b0101_0100 => OpCode::BNE(target),
                 0b0101_0110 => OpCode::BG(target),
                 0b0101_1000 => OpCode::BL(target),
-                _ => return Err(Error::InvalidRegisterAddress(ins)),
+                0b0101_1010 => OpCode::BHI(target),
+                0b0101_1100 => OpCode::BLO(target),
+                _ => return Err(Error::InvalidBranchOpCode(ins)),
             }
         } else if Self::is_src_value(ins) {
             let target: Register = target.try_into()?;
@@ -127,3 +355,90 @@ impl TryFrom<[u8; 3]> for OpCode {
         Ok(val)
     }
 }
+
+#[cfg(test)]
+pub(crate) mod test {
+    use proptest::{prelude::*, sample::select};
+    use strum::IntoEnumIterator;
+
+    use crate::{assembler::assemble, registers::Register};
+
+    use super::{Isa, OpCode};
+
+    /// Registers of the classic ISA
+    fn register() -> impl Strategy<Value = Register> {
+        select(Register::iter().filter(|r| !r.is_extended()).collect::<Vec<_>>())
+    }
+
+    fn any_register() -> impl Strategy<Value = Register> {
+        select(Register::iter().collect::<Vec<_>>())
+    }
+
+    /// Every op code that can be decoded from bytes
+    pub(crate) fn op_code() -> impl Strategy<Value = OpCode> {
+        let rr: [fn(Register, Register) -> OpCode; 9] = [
+            OpCode::ADD, OpCode::SUB, OpCode::LSH, OpCode::RSH, OpCode::AND, OpCode::OR, OpCode::XOR, OpCode::CMP, OpCode::MOV,
+        ];
+        let rv: [fn(Register, u8) -> OpCode; 9] = [
+            OpCode::ADDV, OpCode::SUBV, OpCode::LSHV, OpCode::RSHV, OpCode::ANDV, OpCode::ORV, OpCode::XORV, OpCode::CMPV, OpCode::MOVV,
+        ];
+        let b: [fn(u8) -> OpCode; 7] = [OpCode::BR, OpCode::BE, OpCode::BNE, OpCode::BG, OpCode::BL, OpCode::BHI, OpCode::BLO];
+        prop_oneof![
+            Just(OpCode::HLT),
+            (select(rr.to_vec()), register(), register()).prop_map(|(f, t, s)| f(t, s)),
+            (select(rv.to_vec()), register(), any::<u8>()).prop_map(|(f, t, v)| f(t, v)),
+            (select(b.to_vec()), any::<u8>()).prop_map(|(f, v)| f(v)),
+        ]
+    }
+
+    /// Op codes only the extended ISA decodes
+    pub(crate) fn extended_op_code() -> impl Strategy<Value = OpCode> {
+        prop_oneof![
+            (any_register(), any_register()).prop_map(|(t, s)| OpCode::LD(t, s)),
+            (any_register(), any::<u8>()).prop_map(|(t, v)| OpCode::LDV(t, v)),
+            (any_register(), any_register()).prop_map(|(t, s)| OpCode::ST(t, s)),
+            (any::<u8>(), any_register()).prop_map(|(v, s)| OpCode::STV(v, s)),
+            any::<u8>().prop_map(OpCode::CALL),
+            Just(OpCode::RET),
+            any_register().prop_map(OpCode::PUSH),
+            any_register().prop_map(OpCode::POP),
+            any::<u16>().prop_map(OpCode::BRF),
+            any_register().prop_map(|s| OpCode::MOV(Register::PG, s)),
+            any_register().prop_map(|t| OpCode::MOV(t, Register::TM)),
+            any::<u8>().prop_map(|v| OpCode::ADDV(Register::PG, v)),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn test_decode_encode(op_code in op_code()) {
+            let bytes: [u8; 3] = op_code.into();
+            prop_assert_eq!(OpCode::try_from(bytes).expect("failed to decode"), op_code);
+            prop_assert_eq!(OpCode::decode(bytes, Isa::Extended).expect("failed to decode"), op_code);
+        }
+
+        #[test]
+        fn test_decode_extended(op_code in extended_op_code()) {
+            let bytes = op_code.encode();
+            prop_assert_eq!(OpCode::decode(bytes, Isa::Extended).expect("failed to decode"), op_code);
+            prop_assert!(OpCode::decode(bytes, Isa::Classic).is_err());
+        }
+
+        #[test]
+        fn test_encode_decode(bytes in any::<[u8; 3]>()) {
+            if let Ok(op_code) = OpCode::try_from(bytes) {
+                let encoded = op_code.encode();
+                // branches ignore their third byte and encode it as 0
+                let expected = if op_code.branch_target().is_some() { [bytes[0], bytes[1], 0] } else { bytes };
+                prop_assert_eq!(encoded, expected);
+                prop_assert_eq!(OpCode::try_from(encoded).expect("failed to decode"), op_code);
+            }
+        }
+
+        #[test]
+        fn test_display_assembles(op_code in prop_oneof![op_code(), extended_op_code()]) {
+            let source = op_code.to_string();
+            prop_assert_eq!(assemble(&source, Isa::Extended).expect("failed to assemble"), vec![op_code], "{}", source);
+        }
+    }
+}
@@ -1,40 +1,102 @@
 use std::{fs, path::Path};
 
-use crate::{error::{Error,Result}, op_code::OpCode};
+use crate::{error::{Error,Result}, op_code::{Isa, OpCode}};
 
+#[derive(Debug)]
 pub struct Program {
     name: String,
     op_codes: Vec<OpCode>,
+    isa: Isa,
 }
 
 impl Program {
+    /// Decodes a program with the classic ISA
     pub fn new(name: &str, bytes: &[u8]) -> Result<Self> {
-        if bytes.len() % 3 != 0 || bytes.is_empty() {
-            return Err(Error::InvalidProgram);
-        }
+        Self::with_isa(name, bytes, Isa::Classic)
+    }
 
-        let mut op_codes = Vec::with_capacity(bytes.len() / 3);
-        for window in bytes.chunks_exact(3) {
-            if let (Some(ins), Some(tar), Some(src)) = (window.first(), window.get(1), window.get(2))
-            {
-                op_codes.push([*ins, *tar, *src].try_into()?);
-            } else {
-                return Err(Error::InvalidProgram);
-            }
+    /// Decodes a program, rejecting programs longer than PC can address with the ISA
+    pub fn with_isa(name: &str, bytes: &[u8], isa: Isa) -> Result<Self> {
+        if !bytes.len().is_multiple_of(3) || bytes.is_empty() {
+            return Err(Error::InvalidProgramLength(bytes.len()));
         }
-        
+        if bytes.len() / 3 > isa.max_len() {
+            return Err(Error::ProgramTooLong { len: bytes.len() / 3, max: isa.max_len() });
+        }
+
+        let op_codes = bytes
+            .chunks_exact(3)
+            .enumerate()
+            .map(|(index, window)| decode(index, window, isa))
+            .collect::<Result<Vec<_>>>()?;
+
         Ok(Program {
             name: name.into(),
             op_codes,
+            isa,
         })
     }
 
-    pub fn from_path(path: &Path) -> Result<Self> {
-        let name = path.file_name()
-            .map(|f| f.to_string_lossy())
-            .unwrap_or("UNKNOWN".into());
+    /// Decodes the whole program instead of stopping at the first error. Instructions that fail
+    /// to decode are replaced by [`OpCode::TRAP`] and trailing bytes are dropped, every problem
+    /// found is returned with the program, including branches past the end of the program and
+    /// instructions PC cannot address
+    pub fn new_lenient(name: &str, bytes: &[u8], isa: Isa) -> (Self, Vec<Error>) {
+        let mut errors = Vec::new();
+        if bytes.is_empty() {
+            errors.push(Error::InvalidProgramLength(0));
+        }
+        if bytes.len() / 3 > isa.max_len() {
+            errors.push(Error::ProgramTooLong { len: bytes.len() / 3, max: isa.max_len() });
+        }
+
+        let chunks = bytes.chunks_exact(3);
+        let rest = chunks.remainder();
+        let op_codes: Vec<OpCode> = chunks
+            .enumerate()
+            .map(|(index, window)| {
+                decode(index, window, isa).unwrap_or_else(|e| {
+                    errors.push(e);
+                    OpCode::TRAP([window[0], window[1], window[2]])
+                })
+            })
+            .collect();
+        if !rest.is_empty() {
+            errors.push(Error::TruncatedInstruction {
+                offset: bytes.len() - rest.len(),
+                bytes: rest.to_vec(),
+            });
+        }
+
+        let len = op_codes.len();
+        for (index, op_code) in op_codes.iter().enumerate() {
+            if let Some(target) = op_code.jump_target(index).filter(|&t| t >= len) {
+                errors.push(Error::BranchOutOfRange { index, target, len });
+            }
+        }
+
+        let program = Program {
+            name: name.into(),
+            op_codes,
+            isa,
+        };
+        (program, errors)
+    }
+
+    /// Every problem [`Program::new_lenient`] finds in the bytes, empty for a valid program
+    pub fn validate(bytes: &[u8], isa: Isa) -> Vec<Error> {
+        Self::new_lenient("", bytes, isa).1
+    }
+
+    pub fn from_path(path: &Path, isa: Isa) -> Result<Self> {
         let bytes = fs::read(path)?;
-        Self::new(&name, &bytes)
+        Self::with_isa(&file_name(path), &bytes, isa)
+    }
+
+    /// Reads a program with [`Program::new_lenient`]
+    pub fn from_path_lenient(path: &Path, isa: Isa) -> Result<(Self, Vec<Error>)> {
+        let bytes = fs::read(path)?;
+        Ok(Self::new_lenient(&file_name(path), &bytes, isa))
     }
 
     pub fn get_readable_program(&self) -> String {
@@ -61,6 +123,18 @@ impl Program {
             })
     }
 
+    pub fn op_codes(&self) -> &[OpCode] {
+        &self.op_codes
+    }
+
+    pub fn len(&self) -> usize {
+        self.op_codes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.op_codes.is_empty()
+    }
+
     pub fn get_ins(&self, i: usize) -> Option<&OpCode> {
         self.op_codes.get(i)
     }
@@ -68,4 +142,166 @@ impl Program {
     pub fn get_name(&self) -> String {
         self.name.clone()
     }
+
+    /// Instruction set the program was decoded with
+    pub fn isa(&self) -> Isa {
+        self.isa
+    }
+
+    /// Encodes the program back into the bytes [`Program::new`] reads
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.op_codes.iter().flat_map(OpCode::encode).collect()
+    }
+
+    /// FNV-1a hash of the encoded program, used to check a snapshot belongs to it
+    pub fn hash(&self) -> u64 {
+        self.op_codes
+            .iter()
+            .flat_map(OpCode::encode)
+            .fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+                (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+            })
+    }
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|f| f.to_string_lossy())
+        .unwrap_or("UNKNOWN".into())
+        .into_owned()
+}
+
+fn decode(index: usize, window: &[u8], isa: Isa) -> Result<OpCode> {
+    let bytes = [window[0], window[1], window[2]];
+    OpCode::decode(bytes, isa).map_err(|e| Error::InvalidInstruction {
+        index,
+        offset: index * 3,
+        bytes,
+        source: Box::new(e),
+    })
+}
+
+/// Bytes per row of [`hexdump`], four instructions
+const HEXDUMP_ROW: usize = 12;
+
+/// Hexdump of the rows around `offset` with the instruction at `offset` marked,
+/// used to point at invalid bytes in a program
+pub fn hexdump(bytes: &[u8], offset: usize) -> String {
+    let row = offset / HEXDUMP_ROW;
+    let mut dump = String::new();
+    for r in row.saturating_sub(1)..=row + 1 {
+        let start = r * HEXDUMP_ROW;
+        if start >= bytes.len() {
+            break;
+        }
+        let line: Vec<String> = bytes[start..bytes.len().min(start + HEXDUMP_ROW)]
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect();
+        dump.push_str(&format!("{start:06x}: {}\n", line.join(" ")));
+        if r == row {
+            let column = 8 + 3 * (offset - start);
+            let marked = 3.min(bytes.len() - offset);
+            dump.push_str(&format!("{}{}\n", " ".repeat(column), vec!["^^"; marked].join(" ")));
+        }
+    }
+    dump
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+
+    use crate::{
+        error::Error,
+        op_code::{test::op_code, Isa, OpCode},
+        registers::Register,
+    };
+
+    use super::{hexdump, Program};
+
+    #[test]
+    fn test_invalid_instruction() {
+        let mut bytes = include_bytes!("../assets/hi.bin").to_vec();
+        bytes[15..18].copy_from_slice(&[0x57, 0x00, 0x00]);
+        let err = Program::new("hi.bin", &bytes).expect_err("loaded an invalid program");
+        assert_eq!(err.to_string(), "Instruction 5 at byte offset 0x000f [57 00 00] is invalid");
+        let Error::InvalidInstruction { index: 5, offset: 15, source, .. } = err else {
+            panic!("unexpected error {err:?}");
+        };
+        assert!(matches!(*source, Error::InvalidBranchOpCode(0x57)), "{source}");
+
+        let expected = r#"000000: 60 00 10 10 00 11 41 00 00 54 09 00
+00000c: 61 12 00 57 00 00 61 14 00 61 15 00
+                 ^^ ^^ ^^
+000018: 50 00 00 61 13 06 61 14 74 50 00 00
+"#;
+        assert_eq!(hexdump(&bytes, 15), expected);
+
+        let err = Program::new("hi.bin", &bytes[..10]).expect_err("loaded a truncated program");
+        assert!(matches!(err, Error::InvalidProgramLength(10)), "{err}");
+    }
+
+    #[test]
+    fn test_lenient() {
+        let mut bytes = include_bytes!("../assets/hi.bin").to_vec();
+        bytes[1] = 0x20;
+        bytes[15] = 0x57;
+        bytes[34] = 40;
+        bytes.push(0x61);
+        let errors: Vec<String> = Program::validate(&bytes, Isa::Classic).iter().map(ToString::to_string).collect();
+        assert_eq!(
+            errors,
+            [
+                "Instruction 0 at byte offset 0x0000 [60 20 10] is invalid",
+                "Instruction 5 at byte offset 0x000f [57 13 00] is invalid",
+                "1 trailing bytes at byte offset 0x0024 do not form an instruction",
+                "Instruction 11 branches to 40 which is past the end of the program at 12",
+            ]
+        );
+
+        let (program, _) = Program::new_lenient("hi.bin", &bytes, Isa::Classic);
+        assert_eq!(program.len(), 12);
+        assert_eq!(program.get_ins(5), Some(&OpCode::TRAP([0x57, 0x13, 0x00])));
+        assert!(Program::validate(include_bytes!("../assets/demo1.bin"), Isa::Classic).is_empty());
+
+        // a branch to the index right after the last instruction faults too
+        let errors = Program::validate(&OpCode::BR(1).encode(), Isa::Classic);
+        assert!(matches!(errors[..], [Error::BranchOutOfRange { index: 0, target: 1, len: 1 }]), "{errors:?}");
+        let bytes = [OpCode::BR(1).encode(), OpCode::HLT.encode()].concat();
+        assert!(Program::validate(&bytes, Isa::Classic).is_empty());
+    }
+
+    #[test]
+    fn test_too_long() {
+        let bytes = OpCode::MOVV(Register::R0, 1).encode().repeat(257);
+        let err = Program::new("", &bytes).expect_err("loaded more instructions than PC can address");
+        assert!(matches!(err, Error::ProgramTooLong { len: 257, max: 256 }), "{err}");
+        assert!(Program::new("", &bytes[3..]).is_ok());
+
+        let (program, errors) = Program::new_lenient("", &bytes, Isa::Classic);
+        assert_eq!(program.len(), 257);
+        assert_eq!(errors[0].to_string(), "The program has 257 instructions but PC can only address 256 with this ISA");
+
+        let program = Program::with_isa("", &bytes, Isa::Extended).expect("failed to load paged program");
+        assert_eq!(program.len(), 257);
+    }
+
+    proptest! {
+        #[test]
+        fn test_to_bytes(op_codes in prop::collection::vec(op_code(), 1..64)) {
+            let bytes: Vec<u8> = op_codes.iter().flat_map(|o| o.encode()).collect();
+            let program = Program::new("", &bytes).expect("failed to load program");
+            prop_assert_eq!(program.op_codes(), &op_codes[..]);
+            prop_assert_eq!(program.to_bytes(), bytes);
+        }
+    }
+
+    #[test]
+    fn test_to_bytes_assets() {
+        for bytes in [&include_bytes!("../assets/demo1.bin")[..], &include_bytes!("../assets/demo2.bin")[..]] {
+            let program = Program::new("", bytes).expect("failed to load program");
+            assert_eq!(program.to_bytes(), bytes);
+        }
+    }
 }
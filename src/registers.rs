@@ -1,4 +1,4 @@
-use strum::{AsRefStr, EnumIter, FromRepr};
+use strum::{AsRefStr, EnumCount, EnumIter, EnumString, FromRepr};
 
 use crate::error::Error;
 
@@ -18,7 +18,7 @@ pub enum TgsDisplayValues {
     N9 = 0b0110_1111,
 }
 
-#[derive(Copy, Clone, Debug, AsRefStr, EnumIter, strum::Display, FromRepr)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, AsRefStr, EnumCount, EnumIter, EnumString, strum::Display, FromRepr)]
 #[repr(u8)]
 pub enum Register {
     R0 = 0b0000_0000,
@@ -40,9 +40,18 @@ pub enum Register {
 
     PC = 0b0001_0110,
     CR = 0b0001_0111,
+    /// Page of PC, instruction `PG * 256 + PC` executes next. Extended ISA only
+    PG = 0b0001_1000,
+    /// Frames of emulated time at 60Hz, wrapping. Extended ISA only
+    TM = 0b0001_1001,
 }
 
 impl Register {
+    /// Returns true for registers only the extended ISA has
+    pub fn is_extended(&self) -> bool {
+        matches!(self, Register::PG | Register::TM)
+    }
+
     pub fn get_addr(&self) -> u8 {
         *self as u8
     }
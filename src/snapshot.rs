@@ -0,0 +1,242 @@
+//! Save states of a running machine.
+//!
+//! A snapshot holds every register, the RAM and the stack of a
+//! [`Tgs`](crate::tgs::Tgs), the number of executed instructions and the name
+//! and hash of the program it was taken from. Snapshot files (`.tgss`) use this layout, integers are
+//! little endian:
+//!
+//! | size  | field |
+//! | ----- | ----- |
+//! | 4     | magic `TGSS` |
+//! | 1     | format version, currently 1 |
+//! | 8     | program hash, see [`Program::hash`] |
+//! | 8     | executed instruction count, at most `i64::MAX` |
+//! | 1     | program name length `L` |
+//! | `L`   | program name, UTF-8 |
+//! | 1     | register count `N` |
+//! | `2*N` | `N` pairs of register address and register value |
+//! | 2     | RAM size `M`, 0 for the classic ISA |
+//! | `M`   | RAM contents |
+//! | 1     | stack depth `S` |
+//! | `S`   | stack entries from the bottom |
+//!
+//! Registers are stored by address so a file stays readable when registers
+//! are added, registers missing from the file keep their current value.
+
+use std::{fs, path::Path};
+
+use crate::{
+    error::{Error, Result},
+    program::Program,
+    registers::Register,
+};
+
+const MAGIC: &[u8; 4] = b"TGSS";
+const VERSION: u8 = 1;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Snapshot {
+    pub program_name: String,
+    pub program_hash: u64,
+    pub instructions: u64,
+    pub registers: Vec<(Register, u8)>,
+    /// RAM contents, empty without RAM
+    pub memory: Vec<u8>,
+    /// Stack entries from the bottom
+    pub stack: Vec<u8>,
+}
+
+impl Snapshot {
+    /// Highest instruction count a file can hold, far more than a machine can run, so the cycle
+    /// count of a restored machine never overflows
+    pub const MAX_INSTRUCTIONS: u64 = i64::MAX as u64;
+
+    /// Returns an error if the snapshot was not taken from `program`
+    pub fn check_program(&self, program: &Program) -> Result<()> {
+        if self.program_hash != program.hash() {
+            return Err(Error::SnapshotProgramMismatch {
+                snapshot: self.program_name.clone(),
+                program: program.get_name(),
+            });
+        }
+        Ok(())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let name = self.program_name.as_bytes();
+        let name = &name[..name.len().min(u8::MAX as usize)];
+
+        let mut bytes = Vec::with_capacity(25 + name.len() + 2 * self.registers.len() + self.memory.len() + 1 + self.stack.len());
+        bytes.extend_from_slice(MAGIC);
+        bytes.push(VERSION);
+        bytes.extend_from_slice(&self.program_hash.to_le_bytes());
+        bytes.extend_from_slice(&self.instructions.to_le_bytes());
+        bytes.push(name.len() as u8);
+        bytes.extend_from_slice(name);
+        bytes.push(self.registers.len() as u8);
+        for (register, value) in &self.registers {
+            bytes.push(register.get_addr());
+            bytes.push(*value);
+        }
+        bytes.extend_from_slice(&(self.memory.len() as u16).to_le_bytes());
+        bytes.extend_from_slice(&self.memory);
+        let stack = &self.stack[..self.stack.len().min(u8::MAX as usize)];
+        bytes.push(stack.len() as u8);
+        bytes.extend_from_slice(stack);
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut reader = Reader { bytes };
+        if reader.take(4)? != MAGIC {
+            return Err(Error::InvalidSnapshot("not a TGS snapshot".into()));
+        }
+        let version = reader.u8()?;
+        if version != VERSION {
+            return Err(Error::InvalidSnapshot(format!("unsupported version {version}")));
+        }
+        let program_hash = reader.u64()?;
+        let instructions = reader.u64()?;
+        if instructions > Self::MAX_INSTRUCTIONS {
+            return Err(Error::InvalidSnapshot(format!("instruction count {instructions} is out of range")));
+        }
+        let name_len = reader.u8()? as usize;
+        let program_name = String::from_utf8_lossy(reader.take(name_len)?).into_owned();
+        let register_count = reader.u8()?;
+        let registers = (0..register_count)
+            .map(|_| Ok((Register::try_from(reader.u8()?)?, reader.u8()?)))
+            .collect::<Result<Vec<_>>>()?;
+        let memory_len = reader.u16()? as usize;
+        let memory = reader.take(memory_len)?.to_vec();
+        let depth = reader.u8()? as usize;
+        let stack = reader.take(depth)?.to_vec();
+        if !reader.bytes.is_empty() {
+            return Err(Error::InvalidSnapshot("trailing bytes".into()));
+        }
+
+        Ok(Self {
+            program_name,
+            program_hash,
+            instructions,
+            registers,
+            memory,
+            stack,
+        })
+    }
+
+    pub fn from_path(path: &Path) -> Result<Self> {
+        Self::from_bytes(&fs::read(path)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        Ok(fs::write(path, self.to_bytes())?)
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        if self.bytes.len() < n {
+            return Err(Error::InvalidSnapshot("file is truncated".into()));
+        }
+        let (taken, rest) = self.bytes.split_at(n);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        let mut buf = [0; 2];
+        buf.copy_from_slice(self.take(2)?);
+        Ok(u16::from_le_bytes(buf))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        let mut buf = [0; 8];
+        buf.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(buf))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        debugger::Debugger,
+        error::Error,
+        op_code::{Isa, OpCode},
+        program::Program,
+        registers::Register,
+        tgs::Tgs,
+    };
+
+    use super::Snapshot;
+
+    fn demo1() -> Debugger {
+        let program = Program::new("demo1.bin", include_bytes!("../assets/demo1.bin")).expect("failed to load demo1.bin");
+        Debugger::new(Tgs::new(), program)
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut debugger = demo1();
+        *debugger.tgs_mut().register_mut_ref(Register::BA) = std::num::Wrapping(1);
+        debugger.run(321, None);
+
+        let snapshot = debugger.snapshot();
+        let bytes = snapshot.to_bytes();
+        assert_eq!(&bytes[..5], b"TGSS\x01");
+        assert_eq!(Snapshot::from_bytes(&bytes).expect("failed to read snapshot"), snapshot);
+
+        let mut restored = demo1();
+        restored.restore(&snapshot).expect("failed to restore");
+        assert_eq!(restored.tgs(), debugger.tgs());
+        assert_eq!(restored.instructions(), 321);
+
+        for i in 0..bytes.len() {
+            assert!(Snapshot::from_bytes(&bytes[..i]).is_err());
+        }
+
+        let mut corrupt = bytes.clone();
+        corrupt[13..21].copy_from_slice(&u64::MAX.to_le_bytes());
+        let err = Snapshot::from_bytes(&corrupt).expect_err("read an instruction count that overflows");
+        assert!(matches!(err, Error::InvalidSnapshot(_)), "{err}");
+    }
+
+    #[test]
+    fn test_program_mismatch() {
+        let snapshot = demo1().snapshot();
+        let program = Program::new("hi.bin", include_bytes!("../assets/hi.bin")).expect("failed to load hi.bin");
+        let mut debugger = Debugger::new(Tgs::new(), program);
+        let err = debugger.restore(&snapshot).expect_err("restored into the wrong program");
+        assert!(matches!(err, Error::SnapshotProgramMismatch { .. }), "{err}");
+    }
+
+    #[test]
+    fn test_memory() {
+        // MOV %R0, $7 ; ST [$200], %R0 ; PUSH %R0
+        let bytes = [OpCode::MOVV(Register::R0, 7), OpCode::STV(200, Register::R0), OpCode::PUSH(Register::R0)]
+            .map(|o| o.encode())
+            .concat();
+        let program = || Program::with_isa("", &bytes, Isa::Extended).expect("failed to load program");
+        let mut debugger = Debugger::new(Tgs::with_isa(Isa::Extended), program());
+        debugger.run(3, None);
+        let snapshot = debugger.snapshot();
+        assert_eq!(snapshot.memory[200], 7);
+        assert_eq!(snapshot.stack, [7]);
+        assert_eq!(Snapshot::from_bytes(&snapshot.to_bytes()).expect("failed to read snapshot"), snapshot);
+
+        let mut restored = Debugger::new(Tgs::with_isa(Isa::Extended), program());
+        restored.restore(&snapshot).expect("failed to restore");
+        assert_eq!(restored.tgs(), debugger.tgs());
+
+        let mut classic = Debugger::new(Tgs::new(), program());
+        let err = classic.restore(&snapshot).expect_err("restored RAM into a machine without RAM");
+        assert!(matches!(err, Error::InvalidSnapshot(_)), "{err}");
+    }
+}
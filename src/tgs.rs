@@ -1,10 +1,60 @@
 #![allow(non_snake_case)]
 
-use std::num::Wrapping;
+use std::{fmt::Display, num::Wrapping, time::Instant};
 
-use crate::{op_code::OpCode, program::Program, registers::Register};
+use strum::IntoEnumIterator;
 
-#[derive(Debug)]
+use crate::{
+    clock::Clock,
+    op_code::{Isa, OpCode},
+    program::Program,
+    registers::Register,
+};
+
+/// Why the machine stopped executing instructions
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Fault {
+    /// PC points past the last instruction of the program
+    EndOfProgram(usize),
+    /// PC points at an instruction that could not be decoded
+    InvalidInstruction(usize),
+    /// A CALL or PUSH with a full stack
+    StackOverflow(usize),
+    /// A RET or POP with an empty stack
+    StackUnderflow(usize),
+}
+
+impl Display for Fault {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Fault::EndOfProgram(pc) => write!(f, "PC {pc:03} is past the end of the program"),
+            Fault::InvalidInstruction(pc) => write!(f, "invalid instruction at {pc:03}"),
+            Fault::StackOverflow(pc) => write!(f, "stack overflow at {pc:03}"),
+            Fault::StackUnderflow(pc) => write!(f, "stack underflow at {pc:03}"),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum MachineStatus {
+    #[default]
+    Running,
+    /// Stopped by a HLT instruction
+    Halted,
+    Faulted(Fault),
+}
+
+impl Display for MachineStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MachineStatus::Running => write!(f, "Running"),
+            MachineStatus::Halted => write!(f, "Halted"),
+            MachineStatus::Faulted(fault) => write!(f, "Faulted: {fault}"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Tgs {
     /// R0 to R7 Regisers
     R: [Wrapping<u8>; 8],
@@ -16,19 +66,113 @@ pub struct Tgs {
     PC: Wrapping<u8>,
     /// Comparison Result
     CR: Wrapping<u8>,
+    /// Page of PC
+    PG: Wrapping<u8>,
+    /// Timer, counts frames
+    TM: Wrapping<u8>,
+    /// Emulated instructions per second, TM counts frames of this many cycles over 60
+    hz: u32,
+    /// Number of executed instructions, every instruction takes one cycle
+    cycles: u64,
+    status: MachineStatus,
+    isa: Isa,
+    /// Data memory of the extended ISA, empty for the classic ISA
+    ram: Vec<u8>,
+    /// Return addresses and pushed registers of the extended ISA, the last entry is the top
+    stack: Vec<u8>,
 }
 
 impl Tgs {
+    /// CR flag set by CMP when the operands differ, so CR is zero if and only if they are equal
+    pub const CR_NOT_EQUAL: u8 = 0b0000_0001;
+    /// CR flag set by CMP when the target is less than the source as signed values
+    pub const CR_LESS: u8 = 0b0000_0010;
+    /// CR flag set by CMP when the target is less than the source as unsigned values
+    pub const CR_BELOW: u8 = 0b0000_0100;
+
+    /// Bytes of RAM with the extended ISA, every 8 bit address is valid
+    pub const RAM_SIZE: usize = 256;
+    /// Entries of the stack shared by CALL, RET, PUSH and POP with the extended ISA
+    pub const STACK_SIZE: usize = 16;
+    /// Frames per second of emulated time counted by TM
+    pub const FRAME_HZ: u32 = 60;
+
+    /// A machine for the classic ISA, without RAM
     pub fn new() -> Self {
+        Self::with_isa(Isa::Classic)
+    }
+
+    pub fn with_isa(isa: Isa) -> Self {
+        let ram_size = match isa {
+            Isa::Classic => 0,
+            Isa::Extended => Self::RAM_SIZE,
+        };
         Self {
             R: [Wrapping(0); 8],
             B: [Wrapping(0); 2],
             D: [Wrapping(0); 4],
             PC: Wrapping(0),
             CR: Wrapping(0),
+            PG: Wrapping(0),
+            TM: Wrapping(0),
+            hz: Clock::DEFAULT_HZ,
+            cycles: 0,
+            status: MachineStatus::Running,
+            isa,
+            ram: vec![0; ram_size],
+            stack: Vec::new(),
         }
     }
 
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn set_cycles(&mut self, cycles: u64) {
+        self.cycles = cycles;
+    }
+
+    /// Sets the emulated instructions per second TM counts frames of
+    pub fn set_hz(&mut self, hz: u32) {
+        self.hz = hz.max(1);
+    }
+
+    /// Frames of emulated time elapsed after a number of cycles
+    fn frames(&self, cycles: u64) -> u128 {
+        cycles as u128 * Self::FRAME_HZ as u128 / self.hz as u128
+    }
+
+    pub fn status(&self) -> MachineStatus {
+        self.status
+    }
+
+    /// Sets the status, e.g. back to running after rewinding a halted machine
+    pub fn set_status(&mut self, status: MachineStatus) {
+        self.status = status;
+    }
+
+    pub fn isa(&self) -> Isa {
+        self.isa
+    }
+
+    pub fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    pub fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    /// Stack entries from the bottom to the top
+    pub fn stack(&self) -> &[u8] {
+        &self.stack
+    }
+
+    /// Replaces the stack, entries past [`Tgs::STACK_SIZE`] are dropped
+    pub fn set_stack(&mut self, stack: &[u8]) {
+        self.stack = stack[..stack.len().min(Self::STACK_SIZE)].to_vec();
+    }
+
     pub fn tgs_display(&self) -> [Wrapping<u8>; 4] {
         self.D
     }
@@ -54,6 +198,8 @@ impl Tgs {
 
             Register::PC => self.PC,
             Register::CR => self.CR,
+            Register::PG => self.PG,
+            Register::TM => self.TM,
         }
     }
 
@@ -78,6 +224,8 @@ impl Tgs {
 
             Register::PC => &self.PC,
             Register::CR => &self.CR,
+            Register::PG => &self.PG,
+            Register::TM => &self.TM,
         }
     }
 
@@ -102,38 +250,196 @@ impl Tgs {
 
             Register::PC => &mut self.PC,
             Register::CR => &mut self.CR,
+            Register::PG => &mut self.PG,
+            Register::TM => &mut self.TM,
+        }
+    }
+
+    /// Index of the instruction at PC, `PG * 256 + PC`
+    pub fn address(&self) -> usize {
+        (self.PG.0 as usize) << 8 | self.PC.0 as usize
+    }
+
+    /// Registers of the ISA, in [`Register`] order
+    pub fn registers(&self) -> impl Iterator<Item = Register> {
+        let isa = self.isa;
+        Register::iter().filter(move |r| isa == Isa::Extended || !r.is_extended())
+    }
+
+    /// Returns the value of every register of the ISA, in [`Register`] order
+    pub fn snapshot(&self) -> Vec<(Register, u8)> {
+        self.registers().map(|r| (r, self.register(r).0)).collect()
+    }
+
+    /// Sets every register in `registers`, leaving the others untouched
+    pub fn restore(&mut self, registers: &[(Register, u8)]) {
+        for &(register, value) in registers {
+            *self.register_mut_ref(register) = Wrapping(value);
         }
     }
 
     fn store_cr_rr(&mut self, target: Register, source: Register) {
-        let v = self.register(target).0 as i8 - self.register(source).0 as i8;
-        let v = u8::from_le(v.to_le_bytes()[0]);
-        self.CR = Wrapping(v);
+        self.compare(self.register(target).0, self.register(source).0);
     }
 
     fn store_cr_rv(&mut self, target: Register, source: u8) {
-        let t = self.register(target).0 as i8;
-        let s = source as i8;
-        let v = t.wrapping_sub(s);
-        let v = u8::from_le_bytes(v.to_le_bytes());
-        self.CR = Wrapping(v);
+        self.compare(self.register(target).0, source);
+    }
+
+    /// Sets the CR flags for comparing `t` to `s`
+    fn compare(&mut self, t: u8, s: u8) {
+        let mut cr = 0;
+        if t != s {
+            cr |= Self::CR_NOT_EQUAL;
+        }
+        if (t as i8) < (s as i8) {
+            cr |= Self::CR_LESS;
+        }
+        if t < s {
+            cr |= Self::CR_BELOW;
+        }
+        self.CR = Wrapping(cr);
+    }
+
+    fn cr_flag(&self, flag: u8) -> bool {
+        self.CR.0 & flag != 0
+    }
+
+    /// Shifts by 8 or more clear the register, unlike `Wrapping` shifts which mask the amount
+    fn shift_left(&mut self, target: Register, amount: u8) {
+        let v = self.register(target).0.checked_shl(amount as u32).unwrap_or(0);
+        *self.register_mut_ref(target) = Wrapping(v);
+    }
+
+    fn shift_right(&mut self, target: Register, amount: u8) {
+        let v = self.register(target).0.checked_shr(amount as u32).unwrap_or(0);
+        *self.register_mut_ref(target) = Wrapping(v);
+    }
+
+    /// Loads a RAM byte into the target, returns false and faults if there is no RAM
+    fn load(&mut self, target: Register, addr: u8) -> bool {
+        match self.ram.get(addr as usize) {
+            Some(&v) => {
+                *self.register_mut_ref(target) = Wrapping(v);
+                true
+            }
+            None => {
+                self.fault(Fault::InvalidInstruction);
+                false
+            }
+        }
+    }
+
+    /// Stores a register into RAM, returns false and faults if there is no RAM
+    fn store(&mut self, addr: u8, source: Register) -> bool {
+        let v = self.register(source).0;
+        match self.ram.get_mut(addr as usize) {
+            Some(byte) => {
+                *byte = v;
+                true
+            }
+            None => {
+                self.fault(Fault::InvalidInstruction);
+                false
+            }
+        }
+    }
+
+    /// Pushes onto the stack, returns false and faults if it is full or there is no stack
+    fn push(&mut self, v: u8) -> bool {
+        if self.isa == Isa::Classic {
+            self.fault(Fault::InvalidInstruction);
+            false
+        } else if self.stack.len() == Self::STACK_SIZE {
+            self.fault(Fault::StackOverflow);
+            false
+        } else {
+            self.stack.push(v);
+            true
+        }
+    }
+
+    /// Pops the top of the stack, returns None and faults if it is empty or there is no stack
+    fn pop(&mut self) -> Option<u8> {
+        if self.isa == Isa::Classic {
+            self.fault(Fault::InvalidInstruction);
+            return None;
+        }
+        let v = self.stack.pop();
+        if v.is_none() {
+            self.fault(Fault::StackUnderflow);
+        }
+        v
+    }
+
+    /// Pushes a 16 bit return address as its page then its index, faults unless both entries fit
+    fn push_address(&mut self, address: u16) -> bool {
+        if self.isa == Isa::Extended && self.stack.len() + 2 > Self::STACK_SIZE {
+            self.fault(Fault::StackOverflow);
+            return false;
+        }
+        let [page, index] = address.to_be_bytes();
+        self.push(page) && self.push(index)
+    }
+
+    /// Pops a return address pushed by push_address, faults unless both entries are there
+    fn pop_address(&mut self) -> Option<u16> {
+        if self.isa == Isa::Extended && self.stack.len() < 2 {
+            self.fault(Fault::StackUnderflow);
+            return None;
+        }
+        let index = self.pop()?;
+        let page = self.pop()?;
+        Some(u16::from_be_bytes([page, index]))
     }
 
-    fn get_cr_as_i8(&self) -> i8 {
-        i8::from_le_bytes(self.CR.0.to_le_bytes())
+    /// Stops the machine with a fault at PC, which stays at the faulting instruction
+    fn fault(&mut self, fault: fn(usize) -> Fault) {
+        self.status = MachineStatus::Faulted(fault(self.address()));
     }
 
+    /// PC wraps around in its page with the classic ISA, the extended ISA carries into PG
     fn increment_pc(&mut self) {
         self.PC += 1;
+        if self.PC.0 == 0 && self.isa == Isa::Extended {
+            self.PG += 1;
+        }
+    }
+
+    /// Returns the instruction at PC if the machine is running, faulting if PC is past the end
+    /// of the program or at an instruction that could not be decoded
+    pub fn fetch(&mut self, program: &Program) -> Option<OpCode> {
+        if self.status != MachineStatus::Running {
+            return None;
+        }
+        let pc = self.address();
+        match program.get_ins(pc) {
+            Some(OpCode::TRAP(_)) => {
+                self.status = MachineStatus::Faulted(Fault::InvalidInstruction(pc));
+                None
+            }
+            Some(op_code) => Some(*op_code),
+            None => {
+                self.status = MachineStatus::Faulted(Fault::EndOfProgram(pc));
+                None
+            }
+        }
     }
 
     /// returns true if program_counter should be incremented
     pub fn process_instruction(&mut self, op_code: OpCode) {
+        tracing::trace!(cycle = self.cycles, pc = self.PC.0, %op_code, "process_instruction");
+        self.cycles += 1;
+        // the timer ticks before the instruction executes, so writing TM resets it. Below 60 Hz
+        // an instruction takes more than a frame
+        if self.isa == Isa::Extended {
+            self.TM += (self.frames(self.cycles) - self.frames(self.cycles - 1)) as u8;
+        }
         match op_code {
             OpCode::ADD(t, sr) => {let reg=self.register(sr);*self.register_mut_ref(t) += reg}
             OpCode::SUB(t, sr) => {let reg=self.register(sr);*self.register_mut_ref(t) -= reg}
-            OpCode::LSH(t, sr) => {let reg=self.register(sr);*self.register_mut_ref(t) <<= reg.0 as usize},
-            OpCode::RSH(t, sr) => {let reg=self.register(sr);*self.register_mut_ref(t) >>= reg.0 as usize},
+            OpCode::LSH(t, sr) => {let reg=self.register(sr);self.shift_left(t, reg.0)},
+            OpCode::RSH(t, sr) => {let reg=self.register(sr);self.shift_right(t, reg.0)},
             OpCode::AND(t, sr) => {let reg=self.register(sr);*self.register_mut_ref(t) &= reg}
             OpCode::OR(t, sr) => {let reg=self.register(sr);*self.register_mut_ref(t) |= reg}
             OpCode::XOR(t, sr) => {let reg = self.register(sr);*self.register_mut_ref(t) ^= reg},
@@ -142,8 +448,8 @@ impl Tgs {
 
             OpCode::ADDV(t, sv) => *self.register_mut_ref(t) += sv,
             OpCode::SUBV(t, sv) => *self.register_mut_ref(t) -= sv,
-            OpCode::LSHV(t, sv) => *self.register_mut_ref(t) <<= sv as usize,
-            OpCode::RSHV(t, sv) => *self.register_mut_ref(t) >>= sv as usize,
+            OpCode::LSHV(t, sv) => self.shift_left(t, sv),
+            OpCode::RSHV(t, sv) => self.shift_right(t, sv),
             OpCode::ANDV(t, sv) => *self.register_mut_ref(t) &= sv,
             OpCode::ORV(t, sv) => *self.register_mut_ref(t) |= sv,
             OpCode::XORV(t, sv) => *self.register_mut_ref(t) ^= sv,
@@ -155,39 +461,125 @@ impl Tgs {
                 return;
             }
             OpCode::BE(v) => {
-                if self.get_cr_as_i8() == 0 {
+                if !self.cr_flag(Self::CR_NOT_EQUAL) {
                     self.PC = Wrapping(v);
                     return;
                 }
             }
             OpCode::BNE(v) => {
-                if self.get_cr_as_i8() != 0 {
+                if self.cr_flag(Self::CR_NOT_EQUAL) {
                     self.PC = Wrapping(v);
                     return;
                 }
             }
             OpCode::BG(v) => {
-                if self.get_cr_as_i8() > 0 {
+                if self.cr_flag(Self::CR_NOT_EQUAL) && !self.cr_flag(Self::CR_LESS) {
                     self.PC = Wrapping(v);
                     return;
                 }
             }
             OpCode::BL(v) => {
-                if self.get_cr_as_i8() < 0 {
+                if self.cr_flag(Self::CR_LESS) {
+                    self.PC = Wrapping(v);
+                    return;
+                }
+            }
+            OpCode::BHI(v) => {
+                if self.cr_flag(Self::CR_NOT_EQUAL) && !self.cr_flag(Self::CR_BELOW) {
+                    self.PC = Wrapping(v);
+                    return;
+                }
+            }
+            OpCode::BLO(v) => {
+                if self.cr_flag(Self::CR_BELOW) {
+                    self.PC = Wrapping(v);
+                    return;
+                }
+            }
+
+            OpCode::LD(t, sr) => {
+                let addr = self.register(sr).0;
+                if !self.load(t, addr) {
+                    return;
+                }
+            }
+            OpCode::LDV(t, addr) => {
+                if !self.load(t, addr) {
+                    return;
+                }
+            }
+            OpCode::ST(t, sr) => {
+                let addr = self.register(t).0;
+                if !self.store(addr, sr) {
+                    return;
+                }
+            }
+            OpCode::STV(addr, sr) => {
+                if !self.store(addr, sr) {
+                    return;
+                }
+            }
+
+            OpCode::CALL(v) => {
+                if self.push_address((self.address() as u16).wrapping_add(1)) {
                     self.PC = Wrapping(v);
+                }
+                return;
+            }
+            OpCode::RET => {
+                if let Some(address) = self.pop_address() {
+                    let [page, index] = address.to_be_bytes();
+                    self.PG = Wrapping(page);
+                    self.PC = Wrapping(index);
+                }
+                return;
+            }
+            OpCode::PUSH(sr) => {
+                if !self.push(self.register(sr).0) {
                     return;
                 }
             }
+            OpCode::POP(t) => match self.pop() {
+                Some(v) => *self.register_mut_ref(t) = Wrapping(v),
+                None => return,
+            },
+            OpCode::BRF(v) => {
+                if self.isa == Isa::Classic {
+                    self.fault(Fault::InvalidInstruction);
+                } else {
+                    let [page, pc] = v.to_be_bytes();
+                    self.PG = Wrapping(page);
+                    self.PC = Wrapping(pc);
+                }
+                return;
+            }
+
+            // PC stays at the instruction that stopped the machine
+            OpCode::HLT => {
+                self.status = MachineStatus::Halted;
+                return;
+            }
+            OpCode::TRAP(_) => {
+                self.fault(Fault::InvalidInstruction);
+                return;
+            }
         };
 
         self.increment_pc();
     }
 
-    pub fn run_program(&mut self, program: &Program) {
-        while let Some(instruction) = program.get_ins(self.PC.0 as usize) {
-            std::thread::sleep(std::time::Duration::from_secs(1));
-            self.process_instruction(*instruction)
+    /// Runs the program at the pace of the clock until the machine halts or faults
+    pub fn run_program(&mut self, program: &Program, clock: &mut Clock) -> MachineStatus {
+        let mut due = 0;
+        while let Some(instruction) = self.fetch(program) {
+            while due == 0 {
+                std::thread::sleep(clock.period().min(Clock::MAX_SLEEP));
+                due = clock.due(Instant::now());
+            }
+            self.process_instruction(instruction);
+            due -= 1;
         }
+        self.status
     }
 }
 
@@ -196,3 +588,191 @@ impl Default for Tgs {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        assembler::assemble_to_bytes,
+        op_code::{Isa, OpCode},
+        program::Program,
+        registers::Register,
+    };
+
+    use super::{Fault, MachineStatus, Tgs};
+
+    #[test]
+    fn test_shift() {
+        let mut tgs = Tgs::new();
+        for (op_code, expected) in [
+            (OpCode::MOVV(Register::R0, 0b1011), 0b1011),
+            (OpCode::LSHV(Register::R0, 4), 0b1011_0000),
+            (OpCode::RSHV(Register::R0, 7), 1),
+            (OpCode::LSHV(Register::R0, 9), 0),
+            (OpCode::MOVV(Register::R0, 0xff), 0xff),
+            (OpCode::MOVV(Register::R1, 8), 0xff),
+            (OpCode::RSH(Register::R0, Register::R1), 0),
+        ] {
+            tgs.process_instruction(op_code);
+            assert_eq!(tgs.register(Register::R0).0, expected, "{op_code}");
+        }
+    }
+
+    #[test]
+    fn test_compare() {
+        let taken = |t: u8, s: u8, branch: fn(u8) -> OpCode| {
+            let mut tgs = Tgs::new();
+            tgs.process_instruction(OpCode::MOVV(Register::R0, t));
+            tgs.process_instruction(OpCode::CMPV(Register::R0, s));
+            tgs.process_instruction(branch(100));
+            tgs.register(Register::PC).0 == 100
+        };
+        // BE, BNE, BG, BL, BHI, BLO
+        for (t, s, expected) in [
+            (5, 5, [true, false, false, false, false, false]),
+            (6, 5, [false, true, true, false, true, false]),
+            (5, 6, [false, true, false, true, false, true]),
+            (200, 5, [false, true, false, true, true, false]),
+            (5, 200, [false, true, true, false, false, true]),
+            (127, 128, [false, true, true, false, false, true]),
+            (128, 127, [false, true, false, true, true, false]),
+            (0, 255, [false, true, true, false, false, true]),
+        ] {
+            let branches: [fn(u8) -> OpCode; 6] = [OpCode::BE, OpCode::BNE, OpCode::BG, OpCode::BL, OpCode::BHI, OpCode::BLO];
+            let actual = branches.map(|b| taken(t, s, b));
+            assert_eq!(actual, expected, "CMP {t}, {s}");
+        }
+
+        let mut tgs = Tgs::new();
+        tgs.process_instruction(OpCode::MOVV(Register::R0, 128));
+        tgs.process_instruction(OpCode::MOVV(Register::R1, 127));
+        tgs.process_instruction(OpCode::CMP(Register::R0, Register::R1));
+        assert_eq!(tgs.register(Register::CR).0, Tgs::CR_NOT_EQUAL | Tgs::CR_LESS);
+    }
+
+    #[test]
+    fn test_memory() {
+        let mut tgs = Tgs::with_isa(Isa::Extended);
+        assert_eq!(tgs.ram().len(), Tgs::RAM_SIZE);
+        for op_code in [
+            OpCode::MOVV(Register::R0, 42),
+            OpCode::STV(7, Register::R0),
+            OpCode::MOVV(Register::R1, 7),
+            OpCode::LD(Register::R2, Register::R1),
+            OpCode::ST(Register::R2, Register::R0),
+            OpCode::LDV(Register::R3, 42),
+        ] {
+            tgs.process_instruction(op_code);
+        }
+        assert_eq!(tgs.ram()[7], 42);
+        assert_eq!(tgs.register(Register::R2).0, 42);
+        assert_eq!(tgs.register(Register::R3).0, 42);
+        assert_eq!(tgs.register(Register::PC).0, 6);
+
+        let mut tgs = Tgs::new();
+        assert!(tgs.ram().is_empty());
+        tgs.process_instruction(OpCode::LDV(Register::R0, 0));
+        assert_eq!(tgs.status(), MachineStatus::Faulted(Fault::InvalidInstruction(0)));
+        assert_eq!(tgs.register(Register::PC).0, 0);
+    }
+
+    #[test]
+    fn test_stack() {
+        let source = r#"
+        CALL add2
+        CALL add2
+        HLT
+add2:   PUSH %R1
+        MOV  %R1, $2
+        ADD  %R0, %R1
+        POP  %R1
+        RET
+"#;
+        let bytes = assemble_to_bytes(source, Isa::Extended).expect("failed to assemble");
+        let program = Program::with_isa("", &bytes, Isa::Extended).expect("failed to load program");
+        let mut tgs = Tgs::with_isa(Isa::Extended);
+        tgs.restore(&[(Register::R1, 9)]);
+        while let Some(op_code) = tgs.fetch(&program) {
+            tgs.process_instruction(op_code);
+        }
+        assert_eq!(tgs.status(), MachineStatus::Halted);
+        assert_eq!(tgs.register(Register::R0).0, 4);
+        assert_eq!(tgs.register(Register::R1).0, 9);
+        assert!(tgs.stack().is_empty());
+
+        // recursing forever overflows the stack
+        let program = Program::with_isa("", &OpCode::CALL(0).encode(), Isa::Extended).expect("failed to load program");
+        let mut tgs = Tgs::with_isa(Isa::Extended);
+        while let Some(op_code) = tgs.fetch(&program) {
+            tgs.process_instruction(op_code);
+        }
+        assert_eq!(tgs.status(), MachineStatus::Faulted(Fault::StackOverflow(0)));
+        assert_eq!(tgs.stack().len(), Tgs::STACK_SIZE);
+    }
+
+    #[test]
+    fn test_pages() {
+        let source = format!(
+            r#"
+        BRF  far
+back:
+{}
+far:    ADD  %R1, $1
+        CMP  %R1, $1
+        BNE  done
+        BRF  back
+done:   HLT
+"#,
+            "ADD %R0, $1\n".repeat(299)
+        );
+        let bytes = assemble_to_bytes(&source, Isa::Extended).expect("failed to assemble");
+        let program = Program::with_isa("", &bytes, Isa::Extended).expect("failed to load program");
+
+        let mut tgs = Tgs::with_isa(Isa::Extended);
+        let op_code = tgs.fetch(&program).expect("failed to fetch");
+        tgs.process_instruction(op_code);
+        assert_eq!((tgs.register(Register::PG).0, tgs.register(Register::PC).0), (1, 44));
+        assert_eq!(tgs.address(), 300);
+        while let Some(op_code) = tgs.fetch(&program) {
+            tgs.process_instruction(op_code);
+        }
+        // back to 1 and on through the page boundary, then the near BNE to done in page 1
+        assert_eq!(tgs.status(), MachineStatus::Halted);
+        assert_eq!(tgs.address(), 304);
+        assert_eq!(tgs.register(Register::R0).0, (299 % 256) as u8);
+    }
+
+    #[test]
+    fn test_timer() {
+        let mut tgs = Tgs::with_isa(Isa::Extended);
+        tgs.set_hz(600);
+        for _ in 0..25 {
+            tgs.process_instruction(OpCode::MOVV(Register::R0, 0));
+        }
+        assert_eq!(tgs.register(Register::TM).0, 2);
+
+        // writing TM restarts the count, ticks stay on multiples of 10 cycles
+        tgs.process_instruction(OpCode::MOVV(Register::TM, 0));
+        for _ in 0..3 {
+            tgs.process_instruction(OpCode::MOV(Register::R0, Register::TM));
+        }
+        assert_eq!(tgs.register(Register::R0).0, 0);
+        tgs.process_instruction(OpCode::MOV(Register::R0, Register::TM));
+        assert_eq!(tgs.register(Register::R0).0, 1);
+
+        // frames follow emulated time when hz is not a multiple of 60
+        for (hz, cycles, frames) in [(100, 100, 60), (100, 7, 4), (90, 3, 2), (30, 10, 20), (1, 1, 60)] {
+            let mut tgs = Tgs::with_isa(Isa::Extended);
+            tgs.set_hz(hz);
+            for _ in 0..cycles {
+                tgs.process_instruction(OpCode::MOVV(Register::R0, 0));
+            }
+            assert_eq!(tgs.register(Register::TM).0, frames, "{hz} Hz after {cycles} cycles");
+        }
+
+        let mut tgs = Tgs::new();
+        for _ in 0..1000 {
+            tgs.process_instruction(OpCode::MOVV(Register::R0, 0));
+        }
+        assert_eq!(tgs.register(Register::TM).0, 0);
+    }
+}
@@ -72,3 +72,16 @@ impl StatefulWidget for TgsDisplay {
     }
 }
 
+
+/// Renders 7 segment display values as 3 lines of ASCII, using the same
+/// segment bits as [`TgsDisplay`]
+pub fn render_ascii(values: &[u8]) -> String {
+    let segment = |v: u8, bit: u8, c: char| if v & (1 << bit) != 0 { c } else { ' ' };
+    let mut lines = [String::new(), String::new(), String::new()];
+    for &v in values {
+        lines[0].push_str(&format!(" {}  ", segment(v, 0, '_')));
+        lines[1].push_str(&format!("{}{}{} ", segment(v, 5, '|'), segment(v, 6, '_'), segment(v, 1, '|')));
+        lines[2].push_str(&format!("{}{}{} ", segment(v, 4, '|'), segment(v, 3, '_'), segment(v, 2, '|')));
+    }
+    lines.map(|l| l.trim_end().to_string()).join("\n")
+}
@@ -0,0 +1,213 @@
+//! Execution trace of every instruction with the registers it changed.
+//!
+//! The text format is one line per instruction with the cycle, PC, the
+//! instruction and every changed register except PC as `old->new` in hex:
+//!
+//! ```text
+//!        9 009 MOV %D1, $6      D1:00->06
+//! ```
+//!
+//! The JSON format writes one object per line:
+//!
+//! ```text
+//! {"changes":{"D1":[0,6]},"cycle":9,"op":"MOV %D1, $6","pc":9}
+//! ```
+
+use std::{
+    io::{self, Write},
+    ops::Range,
+    str::FromStr,
+};
+
+use serde_json::{json, Map, Value};
+
+use crate::{op_code::OpCode, registers::Register, tgs::Tgs};
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum TraceFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TraceEntry {
+    /// Cycle the instruction started at
+    pub cycle: u64,
+    pub pc: usize,
+    pub op_code: OpCode,
+    /// Registers other than PC that changed, with their old and new value
+    pub changes: Vec<(Register, u8, u8)>,
+}
+
+impl TraceEntry {
+    pub fn new(op_code: OpCode, before: &Tgs, after: &Tgs) -> Self {
+        let changes = before
+            .registers()
+            .filter(|r| !matches!(r, Register::PC))
+            .map(|r| (r, before.register(r).0, after.register(r).0))
+            .filter(|(_, old, new)| old != new)
+            .collect();
+        Self {
+            cycle: before.cycles(),
+            pc: before.address(),
+            op_code,
+            changes,
+        }
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut line = format!("{:>8} {:03} {:<16}", self.cycle, self.pc, self.op_code.to_string());
+        for (register, old, new) in &self.changes {
+            line.push_str(&format!(" {register}:{old:02x}->{new:02x}"));
+        }
+        line.trim_end().into()
+    }
+
+    pub fn to_json(&self) -> String {
+        let changes: Map<String, Value> = self
+            .changes
+            .iter()
+            .map(|(register, old, new)| (register.to_string(), json!([old, new])))
+            .collect();
+        json!({
+            "cycle": self.cycle,
+            "pc": self.pc,
+            "op": self.op_code.to_string(),
+            "changes": changes,
+        })
+        .to_string()
+    }
+}
+
+/// Limits a trace to a PC range and to instructions changing given registers
+#[derive(Clone, Debug, Default)]
+pub struct TraceFilter {
+    pub pc: Option<Range<usize>>,
+    /// Only trace instructions changing one of these, all instructions if empty
+    pub registers: Vec<Register>,
+}
+
+impl TraceFilter {
+    pub fn matches(&self, entry: &TraceEntry) -> bool {
+        self.pc.as_ref().is_none_or(|pc| pc.contains(&entry.pc))
+            && (self.registers.is_empty() || entry.changes.iter().any(|(r, _, _)| self.registers.contains(r)))
+    }
+}
+
+/// A PC range written like `10..20`, or a single PC like `12`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PcRange(pub Range<usize>);
+
+impl FromStr for PcRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |v: &str| v.trim().parse::<usize>().map_err(|_| format!("invalid PC range '{s}'"));
+        match s.split_once("..") {
+            Some((start, end)) => Ok(Self(parse(start)?..parse(end)?)),
+            None => parse(s).map(|pc| Self(pc..pc + 1)),
+        }
+    }
+}
+
+pub struct Tracer {
+    writer: Box<dyn Write + Send + Sync>,
+    format: TraceFormat,
+    filter: TraceFilter,
+}
+
+impl Tracer {
+    pub fn new(writer: Box<dyn Write + Send + Sync>, format: TraceFormat, filter: TraceFilter) -> Self {
+        Self { writer, format, filter }
+    }
+
+    pub fn record(&mut self, entry: &TraceEntry) -> io::Result<()> {
+        if !self.filter.matches(entry) {
+            return Ok(());
+        }
+        let line = match self.format {
+            TraceFormat::Text => entry.to_text(),
+            TraceFormat::Json => entry.to_json(),
+        };
+        writeln!(self.writer, "{line}")
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+impl std::fmt::Debug for Tracer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Tracer")
+            .field("format", &self.format)
+            .field("filter", &self.filter)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::Write,
+        sync::{Arc, Mutex},
+    };
+
+    use crate::{debugger::Debugger, program::Program, registers::Register, tgs::Tgs};
+
+    use super::{PcRange, TraceFilter, TraceFormat, Tracer};
+
+    #[derive(Clone, Default)]
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().expect("Failed to get lock").write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn trace(format: TraceFormat, filter: TraceFilter) -> String {
+        let program = Program::new("hi.bin", include_bytes!("../assets/hi.bin")).expect("failed to load hi.bin");
+        let mut debugger = Debugger::new(Tgs::new(), program);
+        let buf = SharedBuf::default();
+        debugger.set_tracer(Tracer::new(Box::new(buf.clone()), format, filter));
+        *debugger.tgs_mut().register_mut_ref(Register::BA) = std::num::Wrapping(1);
+        debugger.run(6, None);
+        let bytes = buf.0.lock().expect("Failed to get lock").clone();
+        String::from_utf8(bytes).expect("trace is not utf8")
+    }
+
+    #[test]
+    fn test_text() {
+        let expected = r#"       0 000 MOV %R0, %BA     R0:00->01
+       1 001 ADD %R0, %BB
+       2 002 CMP %R0, $0      CR:00->01
+       3 003 BNE $9
+       4 009 MOV %D1, $6      D1:00->06
+       5 010 MOV %D2, $116    D2:00->74
+"#;
+        assert_eq!(trace(TraceFormat::Text, TraceFilter::default()), expected);
+    }
+
+    #[test]
+    fn test_json_filtered() {
+        let filter = TraceFilter {
+            pc: Some("9..20".parse::<PcRange>().expect("failed to parse").0),
+            registers: vec![Register::D1],
+        };
+        let expected = "{\"changes\":{\"D1\":[0,6]},\"cycle\":4,\"op\":\"MOV %D1, $6\",\"pc\":9}\n";
+        assert_eq!(trace(TraceFormat::Json, filter), expected);
+    }
+
+    #[test]
+    fn test_pc_range() {
+        assert_eq!("12".parse::<PcRange>(), Ok(PcRange(12..13)));
+        assert_eq!("0..4".parse::<PcRange>(), Ok(PcRange(0..4)));
+        assert!("a..4".parse::<PcRange>().is_err());
+    }
+}